@@ -39,101 +39,543 @@ What do you get if you add up all of the invalid IDs?
 
 */
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use regex::Regex;
-use shared::shared_main;
+use serde::Serialize;
+use shared::shared_main_with_options;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap},
     fs::File,
-    io::{BufRead, BufReader},
-    thread::current,
+    io::{self, BufReader, BufWriter, Read, Write},
 };
-use tracing::{info, warn};
+use tracing::{debug, info};
 fn main() -> Result<()> {
-    shared_main(solution1, solution2)
+    shared_main_with_options(solution1, solution2)
 }
 
-fn solution1(input: &File) -> Result<u64> {
-    let range_regex = Regex::new(r"^(\d+)-(\d+)")?;
-    let mut solution = 0;
+#[derive(Debug, clap::Args)]
+struct Options {
+    /// Base the product IDs are written in
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(2..=36))]
+    base: u32,
+    /// Count IDs made of a block repeated exactly this many times, or any of a
+    /// set of counts (e.g. `2,3`), instead of the part's own rule
+    #[arg(long, value_delimiter = ',', conflicts_with = "min_repeats")]
+    repeats: Vec<u32>,
+    /// Count IDs made of a block repeated at least this many times, instead of
+    /// the part's own rule
+    #[arg(long)]
+    min_repeats: Option<u32>,
+    /// Shortest block that can make up an invalid ID
+    #[arg(long, default_value_t = 1)]
+    min_block: u32,
+    /// Print every invalid ID of every range along with the block it repeats
+    #[arg(long)]
+    report: Option<ReportFormat>,
+    /// Most invalid IDs the report may list; larger reports are refused
+    #[arg(long, default_value_t = 100_000, requires = "report")]
+    report_limit: u128,
+}
 
-    let mut input_reader = BufReader::new(input);
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ReportFormat {
+    Json,
+    Table,
+}
 
-    let compute_error_num = |half_num: u64, digits: u64| -> Result<u64> {
-        Ok(half_num + half_num * 10u64.pow(digits.try_into()?))
-    };
+// How many times a block of digits has to repeat for an ID to be invalid.
+#[derive(Debug, Clone)]
+enum Repetition {
+    AnyOf(Vec<u32>),
+    AtLeast(u32),
+}
+
+#[derive(Debug)]
+struct InvalidIdRule {
+    base: u32,
+    repetition: Repetition,
+    min_block: u32,
+}
+
+impl InvalidIdRule {
+    // Builds the rule from the command line, falling back to the part's own
+    // repetition rule when none is given.
+    fn new(options: &Options, default_repetition: Repetition) -> Result<Self> {
+        let repetition = if !options.repeats.is_empty() {
+            Repetition::AnyOf(options.repeats.clone())
+        } else if let Some(min_repeats) = options.min_repeats {
+            Repetition::AtLeast(min_repeats)
+        } else {
+            default_repetition
+        };
 
-    let compute_digits = |num: u64| -> u64 {
-        let mut digits = 0;
-        let mut num = num;
-        while num > 0 {
-            num = num / 10u64;
-            digits += 1;
+        let lowest_repeats = match &repetition {
+            Repetition::AnyOf(repeats) => repeats.iter().copied().min().unwrap_or(2),
+            Repetition::AtLeast(repeats) => *repeats,
+        };
+        ensure!(
+            lowest_repeats >= 2,
+            "A block has to repeat at least twice, got: {lowest_repeats}"
+        );
+        ensure!(options.min_block >= 1, "Blocks have at least one digit");
+        ensure!(
+            (2..=36).contains(&options.base),
+            "IDs can be written in bases 2 to 36, got: {}",
+            options.base
+        );
+
+        Ok(InvalidIdRule {
+            base: options.base,
+            repetition,
+            min_block: options.min_block,
+        })
+    }
+
+    // Block lengths (periods) that make a `length` digit ID invalid.
+    fn periods(&self, length: u32) -> Vec<u32> {
+        divisors(length)
+            .into_iter()
+            .filter(|&period| period < length && period >= self.min_block)
+            .filter(|&period| match &self.repetition {
+                Repetition::AnyOf(repeats) => repeats.contains(&(length / period)),
+                Repetition::AtLeast(repeats) => length / period >= *repeats,
+            })
+            .collect()
+    }
+}
+
+fn compute_digits(num: u128, base: u32) -> u32 {
+    let mut digits = 0;
+    let mut num = num;
+    while num > 0 {
+        num /= base as u128;
+        digits += 1;
+    }
+    digits
+}
+
+fn divisors(num: u32) -> Vec<u32> {
+    (1..=num).filter(|d| num.is_multiple_of(*d)).collect()
+}
+
+fn mobius(num: u32) -> i32 {
+    let mut num = num;
+    let mut result = 1;
+    let mut factor = 2;
+    while factor * factor <= num {
+        if num.is_multiple_of(factor) {
+            num /= factor;
+            if num.is_multiple_of(factor) {
+                return 0; // square factor
+            }
+            result = -result;
         }
-        digits
+        factor += 1;
+    }
+    if num > 1 {
+        result = -result;
+    }
+    result
+}
+
+// Blocks of `period` digits which, repeated to fill `length` digits, give an ID
+// within [range_start, range_end]. Returns the multiplier that repeats a block
+// along with the first and last such block.
+fn compute_block_range(
+    range_start: u128,
+    range_end: u128,
+    base: u32,
+    length: u32,
+    period: u32,
+) -> Option<(u128, u128, u128)> {
+    let base = base as u128;
+    // An ID with period p is block * (1 + base^p + base^2p + ...).
+    let mut multiplier = 0u128;
+    for repeat in 0..length / period {
+        // on overflow every ID of this shape is larger than any u128 range end.
+        multiplier = base
+            .checked_pow(period * repeat)
+            .and_then(|shift| multiplier.checked_add(shift))?;
+    }
+
+    let block_min = base.pow(period - 1);
+    let block_max = base.pow(period) - 1;
+    let first_block = block_min.max(range_start.div_ceil(multiplier));
+    let last_block = block_max.min(range_end / multiplier);
+    (first_block <= last_block).then_some((multiplier, first_block, last_block))
+}
+
+// Count and sum of the `length` digit IDs in [range_start, range_end] made of a
+// `period` digit block repeated to fill the ID (the ID has period `period`, but
+// may also have a smaller one).
+fn compute_periodic_ids(
+    range_start: u128,
+    range_end: u128,
+    base: u32,
+    length: u32,
+    period: u32,
+) -> Result<(u128, u128)> {
+    let Some((multiplier, first_block, last_block)) =
+        compute_block_range(range_start, range_end, base, length, period)
+    else {
+        return Ok((0, 0));
+    };
+
+    // sum of the arithmetic series first_block..=last_block, halving whichever
+    // factor is even to stay within bounds.
+    let count = last_block - first_block + 1;
+    let block_sum = if count.is_multiple_of(2) {
+        (count / 2) * (first_block + last_block)
+    } else {
+        count * ((first_block + last_block) / 2)
     };
+    let sum = block_sum.checked_mul(multiplier).ok_or_else(|| {
+        anyhow!("Invalid ID sum overflows for period {period} of length {length}")
+    })?;
+    Ok((count, sum))
+}
 
-    let mut compute_invalid_code_sum = |range_start: u64, range_end: u64| -> Result<u64> {
-        let mut num_invalid = 0;
-        let mut tmp = range_start;
-        let mut digits = compute_digits(range_start);
+// Count and sum of the `length` digit IDs in [range_start, range_end] whose
+// smallest repeating block divides one of `periods`.
+//
+// With S(q) the sum of IDs having period q, the IDs whose smallest period is
+// exactly p sum to sum(mobius(p / q) * S(q)) over the divisors q of p, so every
+// invalid ID is counted once no matter how many ways it repeats.
+fn compute_repeated_ids(
+    range_start: u128,
+    range_end: u128,
+    base: u32,
+    length: u32,
+    periods: &[u32],
+) -> Result<(u128, u128)> {
+    let mut count = 0u128;
+    let mut sum = 0u128;
+    for smallest_period in divisors(length) {
+        if !periods.iter().any(|period| period % smallest_period == 0) {
+            continue;
+        }
 
-        if digits % 2 != 0 {
-            warn!("Odd number of digits for range: {range_start}-{range_end}");
-            digits += 1;
+        let (mut added_count, mut added_sum) = (0u128, 0u128);
+        let (mut removed_count, mut removed_sum) = (0u128, 0u128);
+        for period in divisors(smallest_period) {
+            let sign = mobius(smallest_period / period);
+            if sign == 0 {
+                continue;
+            }
+            let (period_count, period_sum) =
+                compute_periodic_ids(range_start, range_end, base, length, period)?;
+            let (total_count, total_sum) = if sign > 0 {
+                (&mut added_count, &mut added_sum)
+            } else {
+                (&mut removed_count, &mut removed_sum)
+            };
+            *total_count += period_count;
+            *total_sum = total_sum
+                .checked_add(period_sum)
+                .ok_or_else(|| anyhow!("Invalid ID sum overflows for length {length}"))?;
         }
-        info!("Number of digits in: {range_start}: {digits}");
-
-        let start_half_num = range_start / 10u64.pow(digits as u32 / 2);
-        info!("Half num of: {range_start} is {start_half_num}");
-
-        let mut current_half_num = start_half_num;
-        let error_num_digits = compute_digits(current_half_num);
-        let mut current_error_num = compute_error_num(current_half_num, error_num_digits)?;
-        while current_error_num <= range_end {
-            //info!("Evaluating half num: {current_half_num}, error num:
-            // {current_error_num}");
-            if current_error_num >= range_start && current_error_num <= range_end {
-                num_invalid += 1;
-                solution += current_error_num;
-                info!("Invalid number in range: {range_start}-{range_end}: {current_error_num} Sum: {solution}")
+
+        count += added_count - removed_count;
+        sum = sum
+            .checked_add(added_sum - removed_sum)
+            .ok_or_else(|| anyhow!("Invalid ID sum overflows for length {length}"))?;
+    }
+    Ok((count, sum))
+}
+
+// Count and sum of every invalid ID in [range_start, range_end], without
+// visiting the IDs themselves.
+fn compute_invalid_ids(
+    range_start: u128,
+    range_end: u128,
+    rule: &InvalidIdRule,
+) -> Result<(u128, u128)> {
+    let mut count = 0u128;
+    let mut sum = 0u128;
+    let first_length = compute_digits(range_start, rule.base).max(1);
+    for length in first_length..=compute_digits(range_end, rule.base) {
+        let periods = rule.periods(length);
+        if periods.is_empty() {
+            continue;
+        }
+        debug!("Length {length} is invalid for block lengths: {periods:?}");
+        let (length_count, length_sum) =
+            compute_repeated_ids(range_start, range_end, rule.base, length, &periods)?;
+        count += length_count;
+        sum = sum.checked_add(length_sum).ok_or_else(|| {
+            anyhow!("Invalid ID sum overflows for range {range_start}-{range_end}")
+        })?;
+    }
+    Ok((count, sum))
+}
+
+// An invalid ID along with the shortest block the rule allows to build it.
+#[derive(Debug)]
+struct InvalidId {
+    id: u128,
+    period: u32,
+    repeats: u32,
+}
+
+// Every invalid ID in [range_start, range_end] in ascending order. Unlike
+// `compute_invalid_ids` this visits each ID, so it is only meant for reports.
+fn list_invalid_ids(range_start: u128, range_end: u128, rule: &InvalidIdRule) -> Vec<InvalidId> {
+    let mut invalid_ids = Vec::new();
+    let first_length = compute_digits(range_start, rule.base).max(1);
+    for length in first_length..=compute_digits(range_end, rule.base) {
+        // periods come in ascending order, so the first period to build an ID is
+        // its shortest block.
+        let mut length_ids = BTreeMap::new();
+        for period in rule.periods(length) {
+            let Some((multiplier, first_block, last_block)) =
+                compute_block_range(range_start, range_end, rule.base, length, period)
+            else {
+                continue;
+            };
+            for block in first_block..=last_block {
+                length_ids.entry(block * multiplier).or_insert(period);
             }
-            current_half_num += 1;
+        }
+        invalid_ids.extend(length_ids.into_iter().map(|(id, period)| InvalidId {
+            id,
+            period,
+            repeats: length / period,
+        }));
+    }
+    invalid_ids
+}
+
+fn format_id(num: u128, base: u32) -> String {
+    if num == 0 {
+        return "0".to_string();
+    }
+    let mut digits = Vec::new();
+    let mut num = num;
+    while num > 0 {
+        let digit = (num % base as u128) as u32;
+        digits.push(char::from_digit(digit, base).expect("digit is below the base"));
+        num /= base as u128;
+    }
+    digits.iter().rev().collect()
+}
+
+#[derive(Debug, Serialize)]
+struct RangeReport {
+    range: String,
+    count: u128,
+    sum: u128,
+    invalid_ids: Vec<InvalidIdReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct InvalidIdReport {
+    id: String,
+    block: String,
+    repeats: u32,
+    // other input ranges which also contain this ID
+    shared_with: Vec<String>,
+}
 
-            let error_num_digits = compute_digits(current_half_num);
-            current_error_num = compute_error_num(current_half_num, error_num_digits)?;
+// Lists every invalid ID of every range, explaining each one by its repeating
+// block, and flags IDs found in more than one (overlapping) range. The IDs are
+// counted first, so a report of more than `limit` IDs is refused before any
+// of them are listed.
+fn write_report(
+    output: &mut impl Write,
+    ranges: &[(u128, u128)],
+    rule: &InvalidIdRule,
+    format: ReportFormat,
+    limit: u128,
+) -> Result<()> {
+    let mut total = 0u128;
+    for &(range_start, range_end) in ranges {
+        total = total.saturating_add(compute_invalid_ids(range_start, range_end, rule)?.0);
+        ensure!(
+            total <= limit,
+            "The report would list more than {limit} invalid IDs; \
+             raise --report-limit or narrow the ranges"
+        );
+    }
+
+    let range_ids: Vec<Vec<InvalidId>> = ranges
+        .iter()
+        .map(|&(range_start, range_end)| list_invalid_ids(range_start, range_end, rule))
+        .collect();
+
+    let mut id_ranges: HashMap<u128, Vec<usize>> = HashMap::new();
+    for (range_idx, invalid_ids) in range_ids.iter().enumerate() {
+        for invalid_id in invalid_ids {
+            id_ranges.entry(invalid_id.id).or_default().push(range_idx);
         }
+    }
 
-        Ok(num_invalid)
+    let format_range = |range_idx: usize| {
+        let (range_start, range_end) = ranges[range_idx];
+        format!(
+            "{}-{}",
+            format_id(range_start, rule.base),
+            format_id(range_end, rule.base)
+        )
     };
 
-    loop {
-        // Read until a delimiter
-        let mut range_bytes = Vec::new();
-        let bytes = input_reader.read_until(b',', &mut range_bytes)?;
-        if bytes == 0 {
-            break;
+    let mut reports = Vec::new();
+    for (range_idx, invalid_ids) in range_ids.into_iter().enumerate() {
+        let mut sum = 0u128;
+        let mut invalid_id_reports = Vec::new();
+        for invalid_id in &invalid_ids {
+            sum = sum
+                .checked_add(invalid_id.id)
+                .ok_or_else(|| anyhow!("Invalid ID sum overflows"))?;
+            let id = format_id(invalid_id.id, rule.base);
+            invalid_id_reports.push(InvalidIdReport {
+                block: id[..invalid_id.period as usize].to_string(),
+                id,
+                repeats: invalid_id.repeats,
+                shared_with: id_ranges[&invalid_id.id]
+                    .iter()
+                    .filter(|&&other_idx| other_idx != range_idx)
+                    .map(|&other_idx| format_range(other_idx))
+                    .collect(),
+            });
         }
-        let mut range = String::from_utf8(range_bytes)?;
-        range.pop();
+        reports.push(RangeReport {
+            range: format_range(range_idx),
+            count: invalid_ids.len() as u128,
+            sum,
+            invalid_ids: invalid_id_reports,
+        });
+    }
+
+    match format {
+        ReportFormat::Json => writeln!(output, "{}", serde_json::to_string_pretty(&reports)?)?,
+        ReportFormat::Table => {
+            // the ID and block columns fit the widest of them, up to 128 digits
+            // for u128 IDs in base 2.
+            let invalid_ids = || reports.iter().flat_map(|report| &report.invalid_ids);
+            let id_width = invalid_ids()
+                .map(|invalid_id| invalid_id.id.len())
+                .fold("ID".len(), usize::max);
+            let block_width = invalid_ids()
+                .map(|invalid_id| invalid_id.block.len())
+                .fold("Block".len(), usize::max);
+            for report in &reports {
+                writeln!(
+                    output,
+                    "{}: {} invalid IDs, sum {}",
+                    report.range, report.count, report.sum
+                )?;
+                if report.invalid_ids.is_empty() {
+                    continue;
+                }
+                writeln!(
+                    output,
+                    "    {:<id_width$} {:<block_width$} {:>7}  Shared with",
+                    "ID", "Block", "Repeats"
+                )?;
+                for invalid_id in &report.invalid_ids {
+                    writeln!(
+                        output,
+                        "    {:<id_width$} {:<block_width$} {:>7}  {}",
+                        invalid_id.id,
+                        invalid_id.block,
+                        invalid_id.repeats,
+                        invalid_id.shared_with.join(", ")
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
 
-        // Extract range using a regex.
-        let Some((_, [range_start, range_end])) = range_regex.captures(&range).map(|c| c.extract())
+// Parses a list of `start-end` ranges. Ranges may be separated by commas,
+// whitespace, newlines or any mix of them, as in the wrapped puzzle example.
+fn parse_ranges(input_str: &str, base: u32) -> Result<Vec<(u128, u128)>> {
+    let range_regex = Regex::new(r"^([0-9a-zA-Z]+)-([0-9a-zA-Z]+)$")?;
+    let mut ranges = Vec::new();
+    for (range_idx, range) in input_str
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|range| !range.is_empty())
+        .enumerate()
+    {
+        let Some((_, [range_start, range_end])) = range_regex.captures(range).map(|c| c.extract())
         else {
-            panic!("Unable to parse input range");
+            bail!("Unable to parse input range {}: {range}", range_idx + 1);
         };
 
-        info!("processing: {range_start}-{range_end}");
+        let range_start = u128::from_str_radix(range_start, base)
+            .with_context(|| format!("Invalid base {base} ID in range {range}"))?;
+        let range_end = u128::from_str_radix(range_end, base)
+            .with_context(|| format!("Invalid base {base} ID in range {range}"))?;
+        ensure!(
+            range_start <= range_end,
+            "Range {} starts after it ends: {range}",
+            range_idx + 1
+        );
+        ranges.push((range_start, range_end));
+    }
+    Ok(ranges)
+}
 
-        // Finally, process the extracted range.
-        let range_start: u64 = range_start.parse()?;
-        let range_end: u64 = range_end.parse()?;
+// Sorts the ranges and merges any that overlap, so no ID is in more than one.
+fn merge_ranges(ranges: &[(u128, u128)]) -> Vec<(u128, u128)> {
+    let mut sorted_ranges = ranges.to_vec();
+    sorted_ranges.sort();
+
+    let mut merged_ranges: Vec<(u128, u128)> = Vec::with_capacity(sorted_ranges.len());
+    for (range_start, range_end) in sorted_ranges {
+        match merged_ranges.last_mut() {
+            Some((_, last_end)) if range_start <= *last_end => {
+                debug!("Merging range {range_start}-{range_end} into previous range");
+                *last_end = (*last_end).max(range_end);
+            }
+            _ => merged_ranges.push((range_start, range_end)),
+        }
+    }
+    merged_ranges
+}
 
-        compute_invalid_code_sum(range_start, range_end)?;
+// Sums the invalid IDs of every range, using the part's own repetition rule
+// unless the command line overrides it.
+fn compute_solution(input: &File, options: &Options, repetition: Repetition) -> Result<u64> {
+    let rule = InvalidIdRule::new(options, repetition)?;
+    info!("Using rule: {:?}", rule);
+
+    let mut input_str = String::new();
+    BufReader::new(input).read_to_string(&mut input_str)?;
+    let ranges = parse_ranges(&input_str, rule.base)?;
+    let merged_ranges = merge_ranges(&ranges);
+    if merged_ranges.len() != ranges.len() {
+        info!(
+            "Merged {} input ranges into {} non-overlapping ranges",
+            ranges.len(),
+            merged_ranges.len()
+        );
+    }
+
+    let mut solution = 0u128;
+    for &(range_start, range_end) in &merged_ranges {
+        info!("processing: {range_start}-{range_end}");
+        let (num_invalid, invalid_sum) = compute_invalid_ids(range_start, range_end, &rule)?;
+        solution = solution
+            .checked_add(invalid_sum)
+            .ok_or_else(|| anyhow!("Invalid ID sum overflows"))?;
+        info!("Invalid numbers in range: {range_start}-{range_end}: {num_invalid}, Range sum: {invalid_sum} Sum: {solution}");
+    }
 
-        range.clear();
+    // the report lists the ranges as given, flagging IDs they share.
+    if let Some(format) = options.report {
+        let mut output = BufWriter::new(io::stdout().lock());
+        write_report(&mut output, &ranges, &rule, format, options.report_limit)?;
+        output.flush()?;
     }
-    Ok(solution)
+    solution
+        .try_into()
+        .with_context(|| format!("Solution {solution} does not fit in a u64"))
+}
+
+fn solution1(input: &File, options: &Options) -> Result<u64> {
+    compute_solution(input, options, Repetition::AnyOf(vec![2]))
 }
 
 /*
@@ -163,97 +605,355 @@ What do you get if you add up all of the invalid IDs using these new rules?
 
 */
 
-fn solution2(input: &File) -> Result<u64> {
-    let range_regex = Regex::new(r"^(\d+)-(\d+)")?;
-    let mut solution = 0;
-
-    let mut input_reader = BufReader::new(input);
+fn solution2(input: &File, options: &Options) -> Result<u64> {
+    compute_solution(input, options, Repetition::AtLeast(2))
+}
 
-    let compute_digits = |num: u64| -> u64 {
-        let mut digits = 0;
-        let mut num = num;
-        while num > 0 {
-            num = num / 10u64;
-            digits += 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use shared::test_util::XorShift;
+
+    const EXAMPLE: [(u128, u128); 11] = [
+        (11, 22),
+        (95, 115),
+        (998, 1012),
+        (1188511880, 1188511890),
+        (222220, 222224),
+        (1698522, 1698528),
+        (446443, 446449),
+        (38593856, 38593862),
+        (565653, 565659),
+        (824824821, 824824827),
+        (2121212118, 2121212124),
+    ];
+
+    fn rule(base: u32, repetition: Repetition, min_block: u32) -> InvalidIdRule {
+        InvalidIdRule {
+            base,
+            repetition,
+            min_block,
         }
-        digits
-    };
+    }
 
-    let mut unique_invalid_codes = HashSet::new();
+    // Count and sum of the invalid IDs in the range, checking every ID's digits
+    // for a repeating block. `None` when the sum overflows.
+    fn naive_invalid_ids(
+        range_start: u128,
+        range_end: u128,
+        rule: &InvalidIdRule,
+    ) -> Option<(u128, u128)> {
+        let (mut count, mut sum) = (0u128, 0u128);
+        for id in range_start..=range_end {
+            let digits = format_id(id, rule.base);
+            let invalid = rule.periods(digits.len() as u32).into_iter().any(|period| {
+                let block = &digits[..period as usize];
+                digits
+                    .as_bytes()
+                    .chunks(period as usize)
+                    .all(|chunk| chunk == block.as_bytes())
+            });
+            if invalid {
+                count += 1;
+                sum = sum.checked_add(id)?;
+            }
+        }
+        Some((count, sum))
+    }
 
-    let mut compute_invalid_codes = |range_start: u64, range_end: u64| -> Result<()> {
-        let mut tmp = range_start;
-        let mut digits = compute_digits(range_start);
+    #[rstest]
+    #[case(10, Repetition::AnyOf(vec![2]), 1)]
+    #[case(10, Repetition::AtLeast(2), 1)]
+    #[case(10, Repetition::AtLeast(3), 1)]
+    #[case(10, Repetition::AnyOf(vec![2, 3]), 2)]
+    #[case(2, Repetition::AtLeast(2), 1)]
+    #[case(2, Repetition::AnyOf(vec![4]), 3)]
+    #[case(36, Repetition::AtLeast(2), 1)]
+    fn closed_form_matches_naive(
+        #[case] base: u32,
+        #[case] repetition: Repetition,
+        #[case] min_block: u32,
+    ) {
+        let rule = rule(base, repetition, min_block);
+        let mut random = XorShift::new(base as u64 * 1000 + min_block as u64);
+        for _ in 0..150 {
+            // spans of a few thousand IDs, at every magnitude up to 10^8
+            let magnitude = 10u64.pow(1 + random.below(8) as u32);
+            let range_start = random.below(magnitude) as u128;
+            let range_end = range_start + random.below(3000) as u128;
+            assert_eq!(
+                compute_invalid_ids(range_start, range_end, &rule).ok(),
+                naive_invalid_ids(range_start, range_end, &rule),
+                "{range_start}-{range_end} with {rule:?}"
+            );
+        }
+    }
 
-        info!("Number of digits in: {range_start}: {digits}");
+    #[rstest]
+    // 3402823669209 three times, just below u128::MAX
+    #[case(10, 340282366920934028236692093402823669209)]
+    // all ones, u128::MAX itself
+    #[case(2, u128::MAX)]
+    #[case(16, 0xabcd_abcd_abcd_abcd_abcd_abcd_abcd_abcd)]
+    fn u128_boundary(#[case] base: u32, #[case] id: u128) {
+        let rule = rule(base, Repetition::AtLeast(2), 1);
+        for (range_start, range_end) in [
+            (id - 1000, id.saturating_add(1000)),
+            (u128::MAX - 1000, u128::MAX),
+            (id, id),
+        ] {
+            assert_eq!(
+                compute_invalid_ids(range_start, range_end, &rule).ok(),
+                naive_invalid_ids(range_start, range_end, &rule),
+                "{range_start}-{range_end} in base {base}"
+            );
+        }
+        assert_eq!(compute_invalid_ids(id, id, &rule).unwrap(), (1, id));
+        // far too many IDs to sum, which has to be an error rather than a wrap
+        assert!(compute_invalid_ids(0, u128::MAX, &rule).is_err());
+    }
 
-        let mut digits = compute_digits(range_end);
-        info!("Number of digits in: {range_end}: {digits}");
-        let end_half_num = range_end / 10u64.pow(digits as u32 / 2);
-        info!("Half num of: {range_end} is {end_half_num}");
+    fn options(base: u32, repeats: &[u32], min_repeats: Option<u32>, min_block: u32) -> Options {
+        Options {
+            base,
+            repeats: repeats.to_vec(),
+            min_repeats,
+            min_block,
+            report: None,
+            report_limit: 100_000,
+        }
+    }
 
-        let append_num = |in_num: &mut u64, appendee: u64, appendee_digits: u64| -> Result<()> {
-            *in_num = *in_num * 10u64.pow((appendee_digits).try_into()?) + appendee;
-            Ok(())
-        };
+    #[rstest]
+    // the parts' own rules
+    #[case(options(10, &[], None, 1), 6, vec![3], 0, 99, (9, 495))]
+    #[case(options(10, &[], Some(2), 1), 6, vec![1, 2, 3], 100, 999, (9, 4995))]
+    // an explicit set of repeats, against a minimum
+    #[case(options(10, &[2, 3], None, 1), 6, vec![2, 3], 0, 999, (18, 5490))]
+    #[case(options(10, &[3], None, 1), 4, vec![], 1000, 9999, (0, 0))]
+    #[case(options(10, &[], Some(3), 1), 6, vec![1, 2], 0, 999, (9, 4995))]
+    #[case(options(10, &[], Some(3), 2), 6, vec![2], 0, 999, (0, 0))]
+    #[case(options(10, &[2], None, 2), 4, vec![2], 0, 9999, (90, 101 * 4905))]
+    // binary: 111, 101010 and 111111 repeat a block three times
+    #[case(options(2, &[3], None, 1), 6, vec![2], 0, 63, (3, 7 + 42 + 63))]
+    #[case(options(2, &[], Some(2), 1), 4, vec![1, 2], 0, 15, (4, 3 + 7 + 10 + 15))]
+    // base 36: the 35 two digit IDs 11 to zz
+    #[case(options(36, &[2], None, 1), 2, vec![1], 0, 1295, (35, 37 * 630))]
+    fn rules_from_options(
+        #[case] options: Options,
+        #[case] length: u32,
+        #[case] periods: Vec<u32>,
+        #[case] range_start: u128,
+        #[case] range_end: u128,
+        #[case] expected: (u128, u128),
+    ) {
+        let rule = InvalidIdRule::new(&options, Repetition::AnyOf(vec![2])).unwrap();
+        assert_eq!(rule.periods(length), periods);
+        assert_eq!(
+            compute_invalid_ids(range_start, range_end, &rule).unwrap(),
+            expected
+        );
+        assert_eq!(
+            naive_invalid_ids(range_start, range_end, &rule),
+            Some(expected)
+        );
+    }
 
-        let mut current_digit = 1u64;
-        let mut current_digits = compute_digits(current_digit);
-        let mut current_error_num = 11;
-        while current_digit <= end_half_num + 1 {
-            //while current_error_num <= range_end {
-            //info!("Current digit: {current_digit}, number of digits: {current_digits}");
-            while current_error_num <= range_end {
-                if current_error_num >= range_start {
-                    unique_invalid_codes.insert(current_error_num);
-                    info!("Invalid number in range: {range_start}-{range_end}: {current_error_num} Sum: {solution}")
-                }
+    #[rstest]
+    #[case(options(10, &[1], None, 1), "A block has to repeat at least twice, got: 1")]
+    #[case(options(10, &[3, 1], None, 1), "A block has to repeat at least twice, got: 1")]
+    #[case(options(10, &[], Some(1), 1), "A block has to repeat at least twice, got: 1")]
+    #[case(options(10, &[], Some(0), 1), "A block has to repeat at least twice, got: 0")]
+    #[case(options(10, &[], None, 0), "Blocks have at least one digit")]
+    #[case(options(1, &[], None, 1), "IDs can be written in bases 2 to 36, got: 1")]
+    #[case(options(37, &[], None, 1), "IDs can be written in bases 2 to 36, got: 37")]
+    fn rejected_rules(#[case] options: Options, #[case] message: &str) {
+        let error = InvalidIdRule::new(&options, Repetition::AtLeast(2)).unwrap_err();
+        assert_eq!(error.to_string(), message);
+    }
 
-                append_num(&mut current_error_num, current_digit, current_digits)?;
-            }
-            current_digit += 1;
-            current_digits = compute_digits(current_digit);
-            current_error_num = current_digit;
-            append_num(&mut current_error_num, current_digit, current_digits)?;
+    #[rstest]
+    #[case(&["--base", "1"])]
+    #[case(&["--base", "37"])]
+    #[case(&["--repeats", "2", "--min-repeats", "3"])]
+    fn rejected_command_lines(#[case] args: &[&str]) {
+        #[derive(Debug, clap::Parser)]
+        struct Command {
+            #[command(flatten)]
+            options: Options,
         }
+        let args = std::iter::once("day2").chain(args.iter().copied());
+        assert!(<Command as clap::Parser>::try_parse_from(args).is_err());
+    }
 
-        Ok(())
-    };
+    #[rstest]
+    #[case("11-22,95-115")]
+    #[case("11-22,95-115,")]
+    #[case("11-22,\n95-115\n")]
+    #[case("  11-22 \r\n\t95-115  ")]
+    #[case("11-22 , 95-115,,")]
+    fn range_separators(#[case] text: &str) {
+        assert_eq!(parse_ranges(text, 10).unwrap(), vec![(11, 22), (95, 115)]);
+    }
 
-    loop {
-        // Read until a delimiter
-        let mut range_bytes = Vec::new();
-        let bytes = input_reader.read_until(b',', &mut range_bytes)?;
-        if bytes == 0 {
-            break;
-        }
-        let mut range = String::from_utf8(range_bytes)?;
+    #[test]
+    fn wrapped_example_parses() {
+        let wrapped = "11-22,95-115,998-1012,1188511880-1188511890,222220-222224,
+1698522-1698528,446443-446449,38593856-38593862,565653-565659,
+824824821-824824827,2121212118-2121212124
+";
+        assert_eq!(parse_ranges(wrapped, 10).unwrap(), EXAMPLE);
+        assert_eq!(parse_ranges("ff-1a0", 16).unwrap(), vec![(255, 416)]);
+    }
 
-        info!("Input: {range}");
-        // Extract range using a regex.
-        let Some((_, [range_start, range_end])) = range_regex.captures(&range).map(|c| c.extract())
-        else {
-            panic!("Unable to parse input range");
-        };
+    #[rstest]
+    #[case("11-22,30-20", "Range 2 starts after it ends: 30-20")]
+    #[case("11-22,5x", "Unable to parse input range 2: 5x")]
+    #[case("11-22,3-4-5", "Unable to parse input range 2: 3-4-5")]
+    #[case("11-2a", "Invalid base 10 ID in range 11-2a")]
+    fn range_errors(#[case] text: &str, #[case] message: &str) {
+        let error = parse_ranges(text, 10).unwrap_err();
+        assert_eq!(error.to_string(), message);
+    }
+
+    #[test]
+    fn overlapping_ranges_count_once() {
+        let ranges = parse_ranges("95-115,11-22,11-22,15-30,100-120", 10).unwrap();
+        let merged = merge_ranges(&ranges);
+        assert_eq!(merged, vec![(11, 30), (95, 120)]);
+
+        // 11, 22, 99 and 111 once each, however many ranges hold them
+        let rule = rule(10, Repetition::AtLeast(2), 1);
+        let total: u128 = merged
+            .iter()
+            .map(|&(start, end)| compute_invalid_ids(start, end, &rule).unwrap().1)
+            .sum();
+        assert_eq!(total, 11 + 22 + 99 + 111);
+    }
 
-        // Finally, process the extracted range.
-        let range_start: u64 = range_start.parse()?;
-        let range_end: u64 = range_end.parse()?;
+    #[rstest]
+    #[case(Repetition::AnyOf(vec![2]), 1227775554)]
+    #[case(Repetition::AtLeast(2), 4174379265)]
+    fn example_answers(#[case] repetition: Repetition, #[case] expected: u128) {
+        let rule = rule(10, repetition, 1);
+        let total: u128 = EXAMPLE
+            .iter()
+            .map(|&(range_start, range_end)| {
+                compute_invalid_ids(range_start, range_end, &rule)
+                    .unwrap()
+                    .1
+            })
+            .sum();
+        assert_eq!(total, expected);
+    }
 
-        info!("processing: {range_start}-{range_end}");
+    fn report(
+        ranges: &str,
+        rule: &InvalidIdRule,
+        format: ReportFormat,
+        limit: u128,
+    ) -> Result<String> {
+        let ranges = parse_ranges(ranges, rule.base)?;
+        let mut output = Vec::new();
+        write_report(&mut output, &ranges, rule, format, limit)?;
+        Ok(String::from_utf8(output)?)
+    }
 
-        compute_invalid_codes(range_start, range_end)?;
+    #[test]
+    fn report_table() {
+        let rule = rule(10, Repetition::AtLeast(2), 1);
+        let table = report("11-22,20-33,95-115,1-9", &rule, ReportFormat::Table, 100).unwrap();
+        let header = "    ID  Block Repeats  Shared with";
+        let expected = [
+            "11-22: 2 invalid IDs, sum 33",
+            header,
+            "    11  1           2  ",
+            "    22  2           2  20-33",
+            "20-33: 2 invalid IDs, sum 55",
+            header,
+            "    22  2           2  11-22",
+            "    33  3           2  ",
+            "95-115: 2 invalid IDs, sum 210",
+            header,
+            "    99  9           2  ",
+            "    111 1           3  ",
+            "1-9: 0 invalid IDs, sum 0",
+        ];
+        assert_eq!(table.lines().collect::<Vec<_>>(), expected);
+    }
 
-        range.clear();
+    #[test]
+    fn report_table_wide_ids() {
+        // IDs and blocks longer than their headers widen the columns.
+        let rule = rule(10, Repetition::AnyOf(vec![2]), 1);
+        let ranges = "123456789012345123456789012345-123456789012345123456789012345,1-22";
+        let table = report(ranges, &rule, ReportFormat::Table, 100).unwrap();
+        let header = "    ID                             Block           Repeats  Shared with";
+        let expected = [
+            "123456789012345123456789012345-123456789012345123456789012345: 1 invalid IDs, sum 123456789012345123456789012345",
+            header,
+            "    123456789012345123456789012345 123456789012345       2  ",
+            "1-22: 2 invalid IDs, sum 33",
+            header,
+            "    11                             1                     2  ",
+            "    22                             2                     2  ",
+        ];
+        assert_eq!(table.lines().collect::<Vec<_>>(), expected);
     }
 
-    info!("Unique invalid codes:\n");
-    let mut invalid_code_string = String::new();
-    unique_invalid_codes.iter().for_each(|val| {
-        solution += val;
-        invalid_code_string.push_str(&format!("{}\n", val).to_string());
-    });
-    info!("\n{}", invalid_code_string);
+    #[test]
+    fn report_json() {
+        // base 2: 11 (3) and 1010 (10) overlap in 3-10 and 2-12
+        let rule = rule(2, Repetition::AnyOf(vec![2]), 1);
+        let json = report("11-1010,10-1100", &rule, ReportFormat::Json, 100).unwrap();
+        let reports: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            reports,
+            serde_json::json!([
+                {
+                    "range": "11-1010",
+                    "count": 2,
+                    "sum": 13,
+                    "invalid_ids": [
+                        {"id": "11", "block": "1", "repeats": 2, "shared_with": ["10-1100"]},
+                        {"id": "1010", "block": "10", "repeats": 2, "shared_with": ["10-1100"]},
+                    ],
+                },
+                {
+                    "range": "10-1100",
+                    "count": 2,
+                    "sum": 13,
+                    "invalid_ids": [
+                        {"id": "11", "block": "1", "repeats": 2, "shared_with": ["11-1010"]},
+                        {"id": "1010", "block": "10", "repeats": 2, "shared_with": ["11-1010"]},
+                    ],
+                },
+            ])
+        );
+    }
 
-    Ok(solution)
+    #[rstest]
+    #[case(ReportFormat::Table)]
+    #[case(ReportFormat::Json)]
+    fn report_limit(#[case] format: ReportFormat) {
+        // the example holds 8 invalid IDs for part 1
+        let rule = rule(10, Repetition::AnyOf(vec![2]), 1);
+        let ranges = "11-22,95-115,998-1012,1188511880-1188511890,222220-222224,\
+                      1698522-1698528,446443-446449,38593856-38593862";
+        assert!(report(ranges, &rule, format, 8).is_ok());
+        assert_eq!(
+            report(ranges, &rule, format, 7).unwrap_err().to_string(),
+            "The report would list more than 7 invalid IDs; \
+             raise --report-limit or narrow the ranges"
+        );
+        // a huge range is refused without visiting its IDs
+        let huge = report(
+            "1-1000000000000000000000000000000000000",
+            &rule,
+            format,
+            100_000,
+        );
+        assert!(huge.is_err());
+    }
 }
@@ -31,24 +31,422 @@ There are many batteries in front of you. Find the maximum joltage possible from
 s
 */
 
-use anyhow::Result;
-use regex::Regex;
-use shared::shared_main;
+use anyhow::{anyhow, ensure, Context, Result};
+use shared::shared_main_with_options;
 use std::{
-    collections::HashSet,
     fs::File,
-    io::{BufRead, BufReader},
+    io::{self, BufRead, BufReader, BufWriter, Write},
 };
-use tracing::{info, warn};
+use tracing::info;
 fn main() -> Result<()> {
-    shared_main(solution1, solution2)
+    shared_main_with_options(solution1, solution2)
 }
 
-fn solution1(input: &File) -> Result<u64> {
-    let mut solution = 0;
+#[derive(Debug, clap::Args)]
+struct Options {
+    /// Number of batteries to turn on in each bank, instead of the part's own count
+    #[arg(long)]
+    batteries: Option<usize>,
+    /// How to choose the batteries to turn on in each bank
+    #[arg(long, value_enum, default_value_t = Mode::Max)]
+    mode: Mode,
+    /// Most batteries that can be skipped between two turned on batteries
+    #[arg(long, required_if_eq("mode", "max-gap"))]
+    gap: Option<usize>,
+    /// Positions of batteries that can't be turned on
+    #[arg(long, value_delimiter = ',', required_if_eq("mode", "forbidden"))]
+    forbidden: Vec<usize>,
+    /// Lowest rating of a battery that can be turned on
+    #[arg(long, required_if_eq("mode", "min-rating"))]
+    min_rating: Option<u8>,
+    /// Which distinct joltage to produce, counting down from the largest at 1
+    #[arg(long, required_if_eq("mode", "rank"))]
+    rank: Option<u128>,
+    /// Print which batteries are turned on in each bank, and the first and last
+    /// choice of batteries when several give the same joltage
+    #[arg(long)]
+    report: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Mode {
+    /// Largest joltage
+    Max,
+    /// Smallest joltage
+    Min,
+    /// Largest joltage skipping at most `--gap` batteries between turned on ones
+    MaxGap,
+    /// Largest joltage leaving the `--forbidden` batteries off
+    Forbidden,
+    /// Largest joltage using only batteries rated `--min-rating` or more
+    MinRating,
+    /// The `--rank`-th largest distinct joltage
+    Rank,
+}
+
+// Batteries turned on in a bank. When several choices give the same joltage,
+// `first` and `last` are the positionally first and last of them.
+#[derive(Debug, PartialEq, Eq)]
+struct Selection {
+    first: Vec<usize>,
+    last: Vec<usize>,
+}
+
+// Picks `batteries` joltages from the bank, keeping their order, that form the
+// largest (or smallest) number.
+//
+// Keeps a stack of the digits chosen so far: a digit pops every smaller digit
+// before it while there are still digits left to drop, since a larger digit one
+// place further left always wins. Each digit is pushed and popped at most once.
+fn select_joltage(bank: &[u8], batteries: usize, largest: bool) -> Vec<u8> {
+    let mut droppable = bank.len() - batteries;
+    let mut selected: Vec<u8> = Vec::with_capacity(bank.len());
+    for &joltage in bank {
+        let beats = |last: &u8| {
+            if largest {
+                *last < joltage
+            } else {
+                *last > joltage
+            }
+        };
+        while droppable > 0 && selected.last().is_some_and(beats) {
+            selected.pop();
+            droppable -= 1;
+        }
+        selected.push(joltage);
+    }
+    // anything left to drop comes off the tail.
+    selected.truncate(batteries);
+    selected
+}
+
+// Chooses the batteries to turn on in a bank according to the mode.
+fn select_batteries(bank: &[u8], batteries: usize, options: &Options) -> Result<Selection> {
+    ensure!(
+        batteries <= bank.len(),
+        "Cannot turn on {batteries} batteries in a bank of {}",
+        bank.len()
+    );
+
+    let positions = 0..bank.len();
+    match options.mode {
+        Mode::Max => select_extreme(bank, &positions.collect::<Vec<_>>(), batteries, true),
+        Mode::Min => select_extreme(bank, &positions.collect::<Vec<_>>(), batteries, false),
+        Mode::Forbidden => {
+            let eligible: Vec<usize> = positions
+                .filter(|position| !options.forbidden.contains(position))
+                .collect();
+            select_extreme(bank, &eligible, batteries, true)
+        }
+        Mode::MinRating => {
+            let min_rating = options.min_rating.context("Missing --min-rating")?;
+            let eligible: Vec<usize> = positions
+                .filter(|&position| bank[position] >= min_rating)
+                .collect();
+            select_extreme(bank, &eligible, batteries, true)
+        }
+        Mode::MaxGap => {
+            let gap = options.gap.context("Missing --gap")?;
+            Ok(select_max_joltage_with_gap(bank, batteries, gap))
+        }
+        Mode::Rank => {
+            let rank = options.rank.context("Missing --rank")?;
+            ensure!(rank >= 1, "Ranks start at 1 for the largest joltage");
+            select_ranked_joltage(bank, batteries, rank)
+                .ok_or_else(|| anyhow!("The bank produces fewer than {rank} distinct joltages"))
+        }
+    }
+}
+
+// Largest (or smallest) joltage using only the batteries at the `eligible`
+// positions. Leaving batteries out never changes the order of the rest, so the
+// stack selection still applies to what is left.
+fn select_extreme(
+    bank: &[u8],
+    eligible: &[usize],
+    batteries: usize,
+    largest: bool,
+) -> Result<Selection> {
+    ensure!(
+        batteries <= eligible.len(),
+        "Cannot turn on {batteries} batteries when only {} may be used",
+        eligible.len()
+    );
+
+    let eligible_bank: Vec<u8> = eligible.iter().map(|&position| bank[position]).collect();
+    let joltages = select_joltage(&eligible_bank, batteries, largest);
+    let to_bank_positions = |positions: Vec<usize>| -> Vec<usize> {
+        positions
+            .into_iter()
+            .map(|position| eligible[position])
+            .collect()
+    };
+    Ok(Selection {
+        first: to_bank_positions(select_first_positions(&eligible_bank, &joltages)),
+        last: to_bank_positions(select_last_positions(&eligible_bank, &joltages)),
+    })
+}
+
+// Largest joltage skipping at most `gap` batteries between two turned on ones.
+//
+// Greedily taking the largest digit isn't enough here, since which of several
+// equal digits is taken decides what can follow it. Instead `candidates[j]`
+// holds every position that can be the j-th battery of a selection matching the
+// largest prefix so far, and the next digit is the largest one reachable from
+// any of them. Any battery can be followed by its neighbor, so a position only
+// has to leave enough batteries after it.
+fn select_max_joltage_with_gap(bank: &[u8], batteries: usize, gap: usize) -> Selection {
+    if batteries == 0 {
+        return Selection {
+            first: Vec::new(),
+            last: Vec::new(),
+        };
+    }
+
+    let leaves_room = |position: usize, picked: usize| bank.len() - position >= batteries - picked;
+    let mut candidates: Vec<Vec<bool>> = Vec::with_capacity(batteries);
+    let mut reachable: Vec<bool> = (0..bank.len())
+        .map(|position| leaves_room(position, 0))
+        .collect();
+    for picked in 0..batteries {
+        let best = (0..bank.len())
+            .filter(|&position| reachable[position])
+            .map(|position| bank[position])
+            .max()
+            .expect("a battery is always reachable");
+        let chosen: Vec<bool> = (0..bank.len())
+            .map(|position| reachable[position] && bank[position] == best)
+            .collect();
+
+        // positions within `gap + 1` after any chosen position.
+        reachable = vec![false; bank.len()];
+        let mut reach_end = 0;
+        for position in 0..bank.len() {
+            if reach_end > position && leaves_room(position, picked + 1) {
+                reachable[position] = true;
+            }
+            if chosen[position] {
+                reach_end = reach_end.max(position + gap + 2);
+            }
+        }
+        candidates.push(chosen);
+    }
+
+    // drop candidates that no candidate of the next battery can follow.
+    for picked in (0..batteries - 1).rev() {
+        for position in 0..bank.len() {
+            if candidates[picked][position] {
+                let window = position + 1..(position + gap + 2).min(bank.len());
+                candidates[picked][position] =
+                    window.into_iter().any(|next| candidates[picked + 1][next]);
+            }
+        }
+    }
+
+    let follow = |pick_last: bool| -> Vec<usize> {
+        let mut positions: Vec<usize> = Vec::with_capacity(batteries);
+        for picked_candidates in &candidates {
+            let window = match positions.last() {
+                Some(&previous) => previous + 1..(previous + gap + 2).min(bank.len()),
+                None => 0..bank.len(),
+            };
+            let mut window = window.filter(|&position| picked_candidates[position]);
+            let position = if pick_last {
+                window.next_back()
+            } else {
+                window.next()
+            };
+            positions.push(position.expect("pruned candidates always continue"));
+        }
+        positions
+    };
+    Selection {
+        first: follow(false),
+        last: follow(true),
+    }
+}
+
+// The `rank`-th largest distinct joltage the bank can produce, if there are that
+// many.
+//
+// `distinct[p][j]` counts the distinct joltages of `j` batteries using positions
+// from `p` on. Taking each digit from its first occurrence reaches every
+// joltage exactly once, so the count is the sum over digits of the counts after
+// their next occurrence. Walking digits from 9 down then skips whole blocks of
+// larger joltages until the rank falls inside one.
+fn select_ranked_joltage(bank: &[u8], batteries: usize, rank: u128) -> Option<Selection> {
+    let mut next_digit = vec![[bank.len(); 10]; bank.len() + 1];
+    for position in (0..bank.len()).rev() {
+        next_digit[position] = next_digit[position + 1];
+        next_digit[position][bank[position] as usize] = position;
+    }
+
+    let mut distinct = vec![vec![0u128; batteries + 1]; bank.len() + 1];
+    for position in (0..=bank.len()).rev() {
+        distinct[position][0] = 1;
+        for picked in 1..=batteries {
+            distinct[position][picked] = next_digit[position]
+                .iter()
+                .filter(|&&next| next < bank.len())
+                .fold(0u128, |count, &next| {
+                    count.saturating_add(distinct[next + 1][picked - 1])
+                });
+        }
+    }
+    // ranks past the last distinct joltage, including past the one empty
+    // joltage of no batteries, have nothing to pick.
+    if rank > distinct[0][batteries] {
+        return None;
+    }
+
+    let mut rank = rank;
+    let mut position = 0;
+    let mut joltages = Vec::with_capacity(batteries);
+    for remaining in (0..batteries).rev() {
+        let mut chosen = None;
+        for digit in (0..10).rev() {
+            let next = next_digit[position][digit];
+            if next == bank.len() {
+                continue;
+            }
+            let count = distinct[next + 1][remaining];
+            if rank <= count {
+                chosen = Some(next);
+                break;
+            }
+            rank -= count;
+        }
+        let next = chosen?;
+        joltages.push(bank[next]);
+        position = next + 1;
+    }
+
+    Some(Selection {
+        first: select_first_positions(bank, &joltages),
+        last: select_last_positions(bank, &joltages),
+    })
+}
+
+// Adds the number formed by the joltages to `total`, both written as decimal
+// digits with the most significant first. Joltages of more than 38 batteries
+// don't fit in a u128, so the sum is done digit by digit.
+fn add_joltage(total: &mut Vec<u8>, joltages: &[u8]) {
+    if joltages.len() > total.len() {
+        total.splice(0..0, std::iter::repeat_n(0, joltages.len() - total.len()));
+    }
+    let offset = total.len() - joltages.len();
+    let mut carry = 0;
+    for idx in (0..total.len()).rev() {
+        let joltage = idx.checked_sub(offset).map_or(0, |idx| joltages[idx]);
+        let digit = total[idx] + joltage + carry;
+        total[idx] = digit % 10;
+        carry = digit / 10;
+        if idx < offset && carry == 0 {
+            break;
+        }
+    }
+    if carry > 0 {
+        total.insert(0, carry);
+    }
+}
+
+fn format_joltage(joltages: &[u8]) -> String {
+    joltages
+        .iter()
+        .map(|&joltage| (b'0' + joltage) as char)
+        .collect()
+}
+
+// Positions of the batteries that produce `joltages`, taking each digit from
+// the leftmost battery that still leaves room for the rest. Every other choice
+// of batteries producing the same joltage is positionally later.
+fn select_first_positions(bank: &[u8], joltages: &[u8]) -> Vec<usize> {
+    let mut positions = Vec::with_capacity(joltages.len());
+    let mut position = 0;
+    for &joltage in joltages {
+        while bank[position] != joltage {
+            position += 1;
+        }
+        positions.push(position);
+        position += 1;
+    }
+    positions
+}
+
+// Same as `select_first_positions`, taking each digit from the rightmost battery
+// instead.
+fn select_last_positions(bank: &[u8], joltages: &[u8]) -> Vec<usize> {
+    let mut positions = Vec::with_capacity(joltages.len());
+    let mut position = bank.len();
+    for &joltage in joltages.iter().rev() {
+        position -= 1;
+        while bank[position] != joltage {
+            position -= 1;
+        }
+        positions.push(position);
+    }
+    positions.reverse();
+    positions
+}
+
+// Marks the turned on batteries with a `^` under the bank.
+fn format_positions(bank_len: usize, positions: &[usize]) -> String {
+    let mut marker = vec![b' '; bank_len];
+    positions
+        .iter()
+        .for_each(|&position| marker[position] = b'^');
+    String::from_utf8(marker)
+        .expect("marker is ascii")
+        .trim_end()
+        .to_string()
+}
+
+fn write_selection(
+    output: &mut impl Write,
+    bank_idx: usize,
+    bank: &str,
+    joltages: &[u8],
+    first: &[usize],
+    last: &[usize],
+) -> Result<()> {
+    let format_list = |positions: &[usize]| {
+        positions
+            .iter()
+            .map(|position| position.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
 
-    let mut input_reader = BufReader::new(input);
+    writeln!(output, "Bank {bank_idx}: {}", format_joltage(joltages))?;
+    writeln!(output, "    {bank}")?;
+    writeln!(output, "    {}", format_positions(bank.len(), first))?;
+    writeln!(output, "    Positions: {}", format_list(first))?;
+    if first != last {
+        // the positions above are the first of several tied choices.
+        writeln!(output, "    Tied choices, last: {}", format_list(last))?;
+        writeln!(output, "    {bank}")?;
+        writeln!(output, "    {}", format_positions(bank.len(), last))?;
+    }
+    Ok(())
+}
 
+#[derive(Debug)]
+struct Bank {
+    // 1-based, counting only the non-blank lines
+    number: usize,
+    line: usize,
+    text: String,
+    joltages: Vec<u8>,
+}
+
+// Reads one bank per line, skipping blank lines and tolerating CRLF line
+// endings. Every battery has to be rated 1 to 9; all offending characters are
+// reported together with their bank and column.
+fn parse_banks(mut input_reader: impl BufRead) -> Result<Vec<Bank>> {
+    let mut banks = Vec::new();
+    let mut problems = Vec::new();
+
+    let mut line = 0;
     loop {
         // Read until a delimiter
         let mut range_bytes = Vec::new();
@@ -57,34 +455,140 @@ fn solution1(input: &File) -> Result<u64> {
         if bytes == 0 {
             break;
         }
+        line += 1;
 
         if range_bytes.last() == Some(&delim) {
             range_bytes.pop();
         }
+        if range_bytes.last() == Some(&b'\r') {
+            range_bytes.pop();
+        }
 
-        let mut range = String::from_utf8(range_bytes)?;
-        let mut joltages: Vec<u32> = range
-            .chars()
-            .map(|c| c.to_digit(10).expect("Invalid integer value"))
-            .collect();
+        let text = String::from_utf8(range_bytes)
+            .with_context(|| format!("Line {line} is not valid UTF-8"))?;
+        if text.trim().is_empty() {
+            continue;
+        }
 
-        // iterate through the array of joltages, keeping track of the last relative
-        // maxima.
-        let mut largest_joltage = 0u32;
+        let number = banks.len() + 1;
+        let mut joltages = Vec::with_capacity(text.len());
+        for (column, c) in text.chars().enumerate() {
+            match c.to_digit(10) {
+                Some(joltage @ 1..=9) => joltages.push(joltage as u8),
+                _ => problems.push(format!(
+                    "Bank {number} (line {line}), column {}: invalid battery rating {c:?}",
+                    column + 1
+                )),
+            }
+        }
+        banks.push(Bank {
+            number,
+            line,
+            text,
+            joltages,
+        });
+    }
 
-        let compute_joltage = |a, b| a * 10 + b;
+    ensure!(problems.is_empty(), "{}", problems.join("\n"));
+    Ok(banks)
+}
 
-        let mut maxima = 0u32;
-        for joltage in joltages {
-            largest_joltage = largest_joltage.max(compute_joltage(maxima, joltage));
-            maxima = maxima.max(joltage);
-        }
+// Rejects banks with fewer batteries than are to be turned on, listing every one
+// of them before any bank is solved.
+fn check_bank_lengths(banks: &[Bank], batteries: usize) -> Result<()> {
+    let short_banks: Vec<String> = banks
+        .iter()
+        .filter(|bank| bank.joltages.len() < batteries)
+        .map(|bank| {
+            format!(
+                "Bank {} (line {}) has {} batteries, fewer than the {batteries} to turn on",
+                bank.number,
+                bank.line,
+                bank.joltages.len()
+            )
+        })
+        .collect();
+    ensure!(short_banks.is_empty(), "{}", short_banks.join("\n"));
+    Ok(())
+}
 
-        info!("{range}: {largest_joltage}");
+// Rejects the options of a mode other than the chosen one, which it would
+// otherwise silently ignore.
+fn check_mode_options(options: &Options) -> Result<()> {
+    let mode_options = [
+        ("--gap", options.gap.is_some(), Mode::MaxGap, "max-gap"),
+        (
+            "--forbidden",
+            !options.forbidden.is_empty(),
+            Mode::Forbidden,
+            "forbidden",
+        ),
+        (
+            "--min-rating",
+            options.min_rating.is_some(),
+            Mode::MinRating,
+            "min-rating",
+        ),
+        ("--rank", options.rank.is_some(), Mode::Rank, "rank"),
+    ];
+    for (option, given, mode, mode_name) in mode_options {
+        ensure!(
+            !given || options.mode == mode,
+            "{option} only applies to --mode {mode_name}"
+        );
+    }
+    Ok(())
+}
 
-        solution += largest_joltage;
+// Sums the joltage chosen by the mode for every bank, turning on the part's own
+// number of batteries unless the command line overrides it.
+fn compute_solution(input: &File, options: &Options, batteries: usize) -> Result<u64> {
+    check_mode_options(options)?;
+    let batteries = options.batteries.unwrap_or(batteries);
+    let mut total = Vec::new();
+
+    let banks = parse_banks(BufReader::new(input))?;
+    check_bank_lengths(&banks, batteries)?;
+
+    let mut output = BufWriter::new(io::stdout().lock());
+    for bank in &banks {
+        let selection = select_batteries(&bank.joltages, batteries, options)
+            .with_context(|| format!("Unable to choose batteries in bank {}", bank.number))?;
+        let max_joltage: Vec<u8> = selection
+            .first
+            .iter()
+            .map(|&position| bank.joltages[position])
+            .collect();
+        if options.report {
+            write_selection(
+                &mut output,
+                bank.number,
+                &bank.text,
+                &max_joltage,
+                &selection.first,
+                &selection.last,
+            )?;
+        }
+        add_joltage(&mut total, &max_joltage);
+        info!(
+            "{}: {} Sum: {}",
+            bank.text,
+            format_joltage(&max_joltage),
+            format_joltage(&total)
+        );
     }
-    Ok(solution.into())
+    output.flush()?;
+
+    total
+        .iter()
+        .try_fold(0u64, |sum, &digit| {
+            sum.checked_mul(10)?.checked_add(digit as u64)
+        })
+        .ok_or_else(|| anyhow!("Solution {} does not fit in a u64", format_joltage(&total)))
+}
+
+fn solution1(input: &File, options: &Options) -> Result<u64> {
+    compute_solution(input, options, 2)
 }
 
 /*
@@ -115,88 +619,365 @@ The total output joltage is now much larger: 987654321111 + 811111111119 + 43423
 What is the new total output joltage?
 */
 
-fn solution2(input: &File) -> Result<u64> {
-    let mut solution: u64 = 0;
+fn solution2(input: &File, options: &Options) -> Result<u64> {
+    compute_solution(input, options, 12)
+}
 
-    let mut input_reader: BufReader<&File> = BufReader::new(input);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use shared::test_util::XorShift;
+
+    fn options(mode: Mode) -> Options {
+        Options {
+            batteries: None,
+            mode,
+            gap: Some(1),
+            forbidden: vec![0, 3],
+            min_rating: Some(3),
+            rank: Some(4),
+            report: false,
+        }
+    }
 
-    let _compute_joltage = |joltages: &mut [u8; 12]| -> u64 {
-        let mut sum: u64 = 0;
-        joltages.into_iter().for_each(|j| {
-            sum *= 10;
-            sum += *j as u64;
-        });
-        sum
-    };
+    // Small deterministic banks; digits from 1 to `max_digit`, fewer digits make
+    // for more ties.
+    fn banks(max_digit: u64) -> Vec<Vec<u8>> {
+        let mut random = XorShift::new(0x2545f4914f6cdd1d ^ max_digit);
+        (0..200)
+            .map(|_| {
+                let len = 1 + random.below(9);
+                (0..len)
+                    .map(|_| (1 + random.below(max_digit)) as u8)
+                    .collect()
+            })
+            .collect()
+    }
 
-    // Axiom 1: the largest n digit number you can create from n+1 consecutive
-    // digits is the largest of all possible consecutive arrangements of n digits.
-    let compute_max_joltage_minus_index = |joltages: &[u8; 13]| -> (u64, usize) {
-        // for each index in the sequence, compute the joltage of the number formed if
-        // we remove this number from the sequence.
-        let mut max = 0;
-        let mut max_idx = 0;
-        for idx in 0..13 {
-            let mut sum: u64 = 0;
-            for jdx in 0..13 {
-                if jdx != idx {
-                    sum *= 10;
-                    sum += joltages[jdx as usize] as u64;
-                }
-            }
-            if sum >= max {
-                max = sum;
-                max_idx = idx;
-            }
+    // Every choice of `batteries` positions from `start` on, in lexicographic order.
+    fn combinations(start: usize, len: usize, batteries: usize) -> Vec<Vec<usize>> {
+        if batteries == 0 {
+            return vec![Vec::new()];
         }
+        (start..len)
+            .flat_map(|first| {
+                combinations(first + 1, len, batteries - 1)
+                    .into_iter()
+                    .map(move |mut rest| {
+                        rest.insert(0, first);
+                        rest
+                    })
+            })
+            .collect()
+    }
 
-        // Compute the largest digit possible if you remove one digit
-        (max, max_idx)
-    };
+    fn brute_force(bank: &[u8], batteries: usize, options: &Options) -> Option<Selection> {
+        // joltages of the same length compare like the numbers they form.
+        let joltage =
+            |positions: &Vec<usize>| -> Vec<u8> { positions.iter().map(|&p| bank[p]).collect() };
+        let allowed: Vec<Vec<usize>> = combinations(0, bank.len(), batteries)
+            .into_iter()
+            .filter(|positions| match options.mode {
+                Mode::MaxGap => positions
+                    .windows(2)
+                    .all(|pair| pair[1] - pair[0] - 1 <= options.gap.unwrap()),
+                Mode::Forbidden => positions.iter().all(|p| !options.forbidden.contains(p)),
+                Mode::MinRating => positions
+                    .iter()
+                    .all(|&p| bank[p] >= options.min_rating.unwrap()),
+                _ => true,
+            })
+            .collect();
 
-    loop {
-        // Read until a delimiter
-        let mut range_bytes = Vec::new();
-        let delim = b'\n';
-        let bytes = input_reader.read_until(delim, &mut range_bytes)?;
-        if bytes == 0 {
-            break;
-        }
+        let mut joltages: Vec<Vec<u8>> = allowed.iter().map(joltage).collect();
+        joltages.sort_unstable();
+        joltages.dedup();
+        let target = match options.mode {
+            Mode::Min => joltages.first()?,
+            Mode::Rank => joltages
+                .iter()
+                .rev()
+                .nth(options.rank.unwrap() as usize - 1)?,
+            _ => joltages.last()?,
+        };
+
+        let mut matching = allowed
+            .into_iter()
+            .filter(|positions| joltage(positions) == *target);
+        let first = matching.next()?;
+        let last = matching.next_back().unwrap_or_else(|| first.clone());
+        Some(Selection { first, last })
+    }
 
-        if range_bytes.last() == Some(&delim) {
-            range_bytes.pop();
+    #[rstest]
+    fn matches_brute_force(
+        #[values(
+            Mode::Max,
+            Mode::Min,
+            Mode::MaxGap,
+            Mode::Forbidden,
+            Mode::MinRating,
+            Mode::Rank
+        )]
+        mode: Mode,
+        #[values(2, 9)] max_digit: u64,
+    ) {
+        let options = options(mode);
+        for bank in banks(max_digit) {
+            for batteries in 0..=bank.len() {
+                let expected = brute_force(&bank, batteries, &options);
+                let selection = select_batteries(&bank, batteries, &options).ok();
+                assert_eq!(
+                    selection, expected,
+                    "bank {bank:?}, {batteries} batteries, {mode:?}"
+                );
+            }
         }
+    }
 
-        let range = String::from_utf8(range_bytes)?;
-        let joltages: Vec<u8> = range
-            .chars()
-            .map(|c| c.to_digit(10).expect("Invalid integer value") as u8)
-            .collect();
+    #[rstest]
+    #[case(Mode::MaxGap, "--gap", None)]
+    #[case(Mode::Max, "--gap", Some("--gap only applies to --mode max-gap"))]
+    #[case(Mode::Forbidden, "--forbidden", None)]
+    #[case(
+        Mode::Rank,
+        "--forbidden",
+        Some("--forbidden only applies to --mode forbidden")
+    )]
+    #[case(Mode::MinRating, "--min-rating", None)]
+    #[case(
+        Mode::Min,
+        "--min-rating",
+        Some("--min-rating only applies to --mode min-rating")
+    )]
+    #[case(Mode::Rank, "--rank", None)]
+    #[case(Mode::MaxGap, "--rank", Some("--rank only applies to --mode rank"))]
+    fn mode_options(#[case] mode: Mode, #[case] option: &str, #[case] message: Option<&str>) {
+        // only `option` is given, with the value the helper picks for it.
+        let all = options(mode);
+        let options = Options {
+            gap: all.gap.filter(|_| option == "--gap"),
+            forbidden: if option == "--forbidden" {
+                all.forbidden
+            } else {
+                Vec::new()
+            },
+            min_rating: all.min_rating.filter(|_| option == "--min-rating"),
+            rank: all.rank.filter(|_| option == "--rank"),
+            ..all
+        };
+        assert_eq!(
+            check_mode_options(&options)
+                .err()
+                .map(|error| error.to_string())
+                .as_deref(),
+            message
+        );
+    }
 
-        // create initial array of digits representing our max number plus our incoming
-        let mut max_digits: [u8; 13] = [0; 13];
-        max_digits.copy_from_slice(&joltages[0..13]);
+    #[rstest]
+    #[case("987654321111111", 2, 98)]
+    #[case("811111111111119", 2, 89)]
+    #[case("234234234234278", 2, 78)]
+    #[case("818181911112111", 2, 92)]
+    #[case("987654321111111", 12, 987654321111)]
+    #[case("811111111111119", 12, 811111111119)]
+    #[case("234234234234278", 12, 434234234278)]
+    #[case("818181911112111", 12, 888911112111)]
+    fn example_banks(#[case] bank: &str, #[case] batteries: usize, #[case] expected: u128) {
+        let bank: Vec<u8> = bank.bytes().map(|b| b - b'0').collect();
+        let selection = select_batteries(&bank, batteries, &options(Mode::Max)).unwrap();
+        let joltages: Vec<u8> = selection.first.iter().map(|&p| bank[p]).collect();
+        assert_eq!(format_joltage(&joltages), expected.to_string());
+    }
 
-        let mut max_joltage = 0;
+    #[rstest]
+    // the 9 pairs with the leftmost 8 after it, the last choice with the rightmost
+    #[case("89818", 2, "98", &[1, 2], &[1, 4])]
+    #[case("818181911112111", 2, "92", &[6, 11], &[6, 11])]
+    #[case("11111", 3, "111", &[0, 1, 2], &[2, 3, 4])]
+    fn tie_break(
+        #[case] bank: &str,
+        #[case] batteries: usize,
+        #[case] expected: &str,
+        #[case] first: &[usize],
+        #[case] last: &[usize],
+    ) {
+        let bank: Vec<u8> = bank.bytes().map(|b| b - b'0').collect();
+        let selection = select_batteries(&bank, batteries, &options(Mode::Max)).unwrap();
+        let joltages: Vec<u8> = selection.first.iter().map(|&p| bank[p]).collect();
+        assert_eq!(format_joltage(&joltages), expected);
+        assert_eq!(selection.first, first);
+        assert_eq!(selection.last, last);
+    }
 
-        // for rest of digits in joltages, greedily keep the 12 digits that make the
-        // largest number:
-        for idx in 12..joltages.len() {
-            // append our next digit to evaluate
-            max_digits[12] = joltages[idx];
+    #[rstest]
+    #[case(6, &[0, 2, 3], "^ ^^")]
+    #[case(4, &[3], "   ^")]
+    #[case(3, &[], "")]
+    fn positions_marker(
+        #[case] bank_len: usize,
+        #[case] positions: &[usize],
+        #[case] marker: &str,
+    ) {
+        assert_eq!(format_positions(bank_len, positions), marker);
+    }
 
-            let mut index = 0;
-            // compute the index to remove, and max joltage value if removed
-            (max_joltage, index) = compute_max_joltage_minus_index(&max_digits);
+    #[rstest]
+    #[case(
+        1,
+        "89818",
+        &[
+            "Bank 1: 98",
+            "    89818",
+            "     ^^",
+            "    Positions: 1,2",
+            "    Tied choices, last: 1,4",
+            "    89818",
+            "     ^  ^",
+        ]
+    )]
+    #[case(
+        2,
+        "818181911112111",
+        &[
+            "Bank 2: 92",
+            "    818181911112111",
+            "          ^    ^",
+            "    Positions: 6,11",
+        ]
+    )]
+    fn selection_report(#[case] bank_idx: usize, #[case] bank: &str, #[case] expected: &[&str]) {
+        let joltages: Vec<u8> = bank.bytes().map(|b| b - b'0').collect();
+        let selection = select_batteries(&joltages, 2, &options(Mode::Max)).unwrap();
+        let max_joltage: Vec<u8> = selection.first.iter().map(|&p| joltages[p]).collect();
+        let mut output = Vec::new();
+        write_selection(
+            &mut output,
+            bank_idx,
+            bank,
+            &max_joltage,
+            &selection.first,
+            &selection.last,
+        )
+        .unwrap();
+        let report = String::from_utf8(output).unwrap();
+        assert_eq!(report.lines().collect::<Vec<_>>(), expected);
+    }
 
-            // remove the value at index `index` and slide all indices to the left
-            for jdx in index..12 {
-                max_digits[jdx] = max_digits[jdx + 1];
-            }
+    #[test]
+    fn add_joltage_matches_u128() {
+        let mut random = XorShift::new(42);
+        let mut total = Vec::new();
+        let mut expected = 0u128;
+        for _ in 0..1000 {
+            let len = random.below(20) as usize;
+            let joltages: Vec<u8> = (0..len).map(|_| 1 + random.below(9) as u8).collect();
+            add_joltage(&mut total, &joltages);
+            expected += format!("0{}", format_joltage(&joltages))
+                .parse::<u128>()
+                .unwrap();
+            assert_eq!(format_joltage(&total), expected.to_string());
+        }
+    }
+
+    #[rstest]
+    #[case(&["999", "1"], "1000")]
+    #[case(&["1", "999"], "1000")]
+    #[case(&["5", "5", "90"], "100")]
+    #[case(&[], "")]
+    fn add_joltage_carries(#[case] joltages: &[&str], #[case] expected: &str) {
+        let mut total = Vec::new();
+        for joltage in joltages {
+            add_joltage(
+                &mut total,
+                &joltage.bytes().map(|b| b - b'0').collect::<Vec<_>>(),
+            );
         }
-        solution += max_joltage;
-        info!("Max joltage: {max_joltage} Sum: {solution}");
+        assert_eq!(format_joltage(&total), expected);
     }
 
-    Ok(solution)
+    #[test]
+    fn joltages_past_u128() {
+        // 40 batteries of 45 make numbers of 40 digits, past u128's 38.
+        let banks = [
+            "9".repeat(45),
+            format!("1111{}", "9".repeat(41)),
+            format!("{}1234567", "8".repeat(38)),
+        ];
+        let expected = [
+            "9".repeat(40),
+            "9".repeat(40),
+            format!("{}67", "8".repeat(38)),
+        ];
+        let mut total = Vec::new();
+        for (bank, expected) in banks.iter().zip(&expected) {
+            let bank: Vec<u8> = bank.bytes().map(|b| b - b'0').collect();
+            let selection = select_batteries(&bank, 40, &options(Mode::Max)).unwrap();
+            let joltages: Vec<u8> = selection.first.iter().map(|&p| bank[p]).collect();
+            assert_eq!(format_joltage(&joltages), *expected);
+            add_joltage(&mut total, &joltages);
+        }
+        // 2 * (10^40 - 1) + 88..8867
+        assert_eq!(format_joltage(&total), format!("2{}65", "8".repeat(38)));
+    }
+
+    #[rstest]
+    #[case("12\n345\n")]
+    #[case("12\r\n345\r\n")]
+    #[case("12\r\n345")]
+    #[case("\n12\n\n   \n345\n\n")]
+    #[case("12\r\n\r\n345\r\n\r\n")]
+    fn banks_parse(#[case] input: &str) {
+        let banks = parse_banks(input.as_bytes()).unwrap();
+        let joltages: Vec<&[u8]> = banks.iter().map(|bank| &bank.joltages[..]).collect();
+        assert_eq!(joltages, [&[1, 2][..], &[3, 4, 5][..]]);
+        let texts: Vec<&str> = banks.iter().map(|bank| bank.text.as_str()).collect();
+        assert_eq!(texts, ["12", "345"]);
+        assert_eq!(banks[1].number, 2);
+    }
+
+    #[rstest]
+    #[case("1203\n", "Bank 1 (line 1), column 3: invalid battery rating '0'")]
+    #[case(
+        "12\n\n34 5\n",
+        "Bank 2 (line 3), column 3: invalid battery rating ' '"
+    )]
+    #[case(
+        "12\r\n3x\r\n",
+        "Bank 2 (line 2), column 2: invalid battery rating 'x'"
+    )]
+    #[case(
+        "a1\n11\n1-1b\n",
+        "Bank 1 (line 1), column 1: invalid battery rating 'a'\n\
+         Bank 3 (line 3), column 2: invalid battery rating '-'\n\
+         Bank 3 (line 3), column 4: invalid battery rating 'b'"
+    )]
+    fn bad_ratings(#[case] input: &str, #[case] message: &str) {
+        let error = parse_banks(input.as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), message);
+    }
+
+    #[rstest]
+    #[case(2, None)]
+    #[case(
+        3,
+        Some("Bank 1 (line 1) has 2 batteries, fewer than the 3 to turn on")
+    )]
+    #[case(
+        5,
+        Some(
+            "Bank 1 (line 1) has 2 batteries, fewer than the 5 to turn on\n\
+             Bank 2 (line 3) has 4 batteries, fewer than the 5 to turn on"
+        )
+    )]
+    fn short_banks(#[case] batteries: usize, #[case] message: Option<&str>) {
+        let banks = parse_banks("12\n\n1234\n99999\n".as_bytes()).unwrap();
+        let result = check_bank_lengths(&banks, batteries);
+        assert_eq!(
+            result.err().map(|error| error.to_string()).as_deref(),
+            message
+        );
+    }
 }
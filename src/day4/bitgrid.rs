@@ -0,0 +1,340 @@
+use crate::rules::Rules;
+use crate::Grid;
+use anyhow::{bail, ensure, Result};
+use std::io::BufRead;
+
+// Grid with one bit per cell, 64 cells to a word. Every row starts on a new
+// word and the bits past the end of a row are always 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitGrid {
+    pub width: usize,
+    pub height: usize,
+    words_per_row: usize,
+    words: Vec<u64>,
+}
+
+impl BitGrid {
+    pub fn new(width: usize, height: usize) -> Self {
+        let words_per_row = width.div_ceil(64);
+        BitGrid {
+            width,
+            height,
+            words_per_row,
+            words: vec![0; words_per_row * height],
+        }
+    }
+
+    // Reads the diagram straight into bits, without a byte per cell in between.
+    pub fn read(input: impl BufRead) -> Result<Self> {
+        let mut grid = BitGrid::new(0, 0);
+        for_each_line(input, |row, line| {
+            if row == 0 {
+                grid = BitGrid::new(line.len(), 0);
+            }
+            grid.words
+                .extend_from_slice(&parse_row(line, grid.width, row)?);
+            grid.height += 1;
+            Ok(())
+        })?;
+        Ok(grid)
+    }
+
+    pub fn from_grid(grid: &Grid) -> Self {
+        let mut bit_grid = BitGrid::new(grid.width, grid.height);
+        for (cell, &value) in grid.cells.iter().enumerate() {
+            if value == 1 {
+                bit_grid.set(cell);
+            }
+        }
+        bit_grid
+    }
+
+    #[cfg(test)]
+    pub fn to_grid(&self) -> Grid {
+        Grid {
+            width: self.width,
+            height: self.height,
+            cells: (0..self.width * self.height)
+                .map(|cell| u8::from(self.is_set(cell)))
+                .collect(),
+        }
+    }
+
+    fn word_bit(&self, cell: usize) -> (usize, u64) {
+        let (row, col) = (cell / self.width, cell % self.width);
+        (row * self.words_per_row + col / 64, 1 << (col % 64))
+    }
+
+    pub fn is_set(&self, cell: usize) -> bool {
+        let (word, bit) = self.word_bit(cell);
+        self.words[word] & bit != 0
+    }
+
+    pub fn set(&mut self, cell: usize) {
+        let (word, bit) = self.word_bit(cell);
+        self.words[word] |= bit;
+    }
+
+    pub fn row(&self, row: usize) -> &[u64] {
+        &self.words[row * self.words_per_row..(row + 1) * self.words_per_row]
+    }
+
+    pub fn row_mut(&mut self, row: usize) -> &mut [u64] {
+        &mut self.words[row * self.words_per_row..(row + 1) * self.words_per_row]
+    }
+
+    pub fn count_ones(&self) -> u64 {
+        self.words.iter().map(|word| word.count_ones() as u64).sum()
+    }
+
+    // Indices (row * width + col) of the set cells, in order.
+    pub fn set_cells(&self) -> Vec<usize> {
+        let mut cells = Vec::new();
+        for row in 0..self.height {
+            for (word_idx, &word) in self.row(row).iter().enumerate() {
+                let mut word = word;
+                while word != 0 {
+                    let col = word_idx * 64 + word.trailing_zeros() as usize;
+                    cells.push(row * self.width + col);
+                    word &= word - 1;
+                }
+            }
+        }
+        cells
+    }
+}
+
+// Calls `on_line(row, line)` for each line of the diagram, without the newline
+// or a CRLF line ending's `\r`.
+pub fn for_each_line(
+    mut input: impl BufRead,
+    mut on_line: impl FnMut(usize, &[u8]) -> Result<()>,
+) -> Result<()> {
+    let mut line = Vec::new();
+    let mut row = 0;
+    loop {
+        line.clear();
+        let delim = b'\n';
+        let bytes = input.read_until(delim, &mut line)?;
+        if bytes == 0 {
+            return Ok(());
+        }
+        if line.last() == Some(&delim) {
+            line.pop();
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        on_line(row, &line)?;
+        row += 1;
+    }
+}
+
+// Packs one line of the diagram (without its newline) into `width.div_ceil(64)`
+// words. `row` is only used for the error messages.
+pub fn parse_row(line: &[u8], width: usize, row: usize) -> Result<Vec<u64>> {
+    ensure!(
+        line.len() == width,
+        "Row {} has width {}, expected {width}",
+        row + 1,
+        line.len()
+    );
+    let mut words = vec![0u64; width.div_ceil(64)];
+    for (col, &c) in line.iter().enumerate() {
+        match c {
+            b'@' => words[col / 64] |= 1 << (col % 64),
+            b'.' => {}
+            c => bail!(
+                "Row {}, column {}: invalid cell value {:?}",
+                row + 1,
+                col + 1,
+                c as char
+            ),
+        }
+    }
+    Ok(words)
+}
+
+// out bit c = src bit (c + dx); bits from outside `src` read as 0.
+fn shift_into(src: &[u64], dx: i64, out: &mut [u64]) {
+    let words = src.len() as i64;
+    let word_shift = dx.div_euclid(64);
+    let bit_shift = dx.rem_euclid(64) as u32;
+    let word = |idx: i64| {
+        if (0..words).contains(&idx) {
+            src[idx as usize]
+        } else {
+            0
+        }
+    };
+    for (idx, out_word) in out.iter_mut().enumerate() {
+        let low = word(idx as i64 + word_shift);
+        *out_word = if bit_shift == 0 {
+            low
+        } else {
+            (low >> bit_shift) | (word(idx as i64 + word_shift + 1) << (64 - bit_shift))
+        };
+    }
+}
+
+// Classifies a whole row of cells at a time.
+//
+// Each kernel offset turns into a copy of the neighboring row shifted by the
+// column offset, so bit c of every copy is one neighbor of cell c. The copies
+// are summed into bit-sliced counters (`planes[k]` holds bit k of all 64 counts
+// of a word) with ripple-carry adders made of AND and XOR, and the counts are
+// compared against the threshold the same way. Memory-wise the grid needs one
+// bit per cell; the counters only ever cover one row.
+pub struct RowClassifier<'a> {
+    rules: &'a Rules,
+    planes: Vec<Vec<u64>>,
+    shifted: Vec<u64>,
+    wrapped: Vec<u64>,
+}
+
+impl<'a> RowClassifier<'a> {
+    pub fn new(rules: &'a Rules, words_per_row: usize) -> Self {
+        // enough counter bits to hold both the largest count and the threshold.
+        let total_weight: u64 = rules
+            .offsets
+            .iter()
+            .map(|&(_, _, weight)| weight as u64)
+            .sum();
+        let counter_bits = 64 - total_weight.max(rules.threshold as u64).leading_zeros() as usize;
+        RowClassifier {
+            rules,
+            planes: vec![vec![0; words_per_row]; counter_bits],
+            shifted: vec![0; words_per_row],
+            wrapped: vec![0; words_per_row],
+        }
+    }
+
+    // Writes into `out` the rolls of `row` whose neighbors add up to less than
+    // the threshold.
+    pub fn accessible(&mut self, grid: &BitGrid, row: usize, out: &mut [u64]) {
+        let height = grid.height as i64;
+        let toroidal = self.rules.toroidal;
+        self.classify(
+            grid.width,
+            grid.row(row),
+            |dy| {
+                let neighbor_row = row as i64 + dy;
+                if toroidal {
+                    Some(grid.row(neighbor_row.rem_euclid(height) as usize))
+                } else if (0..height).contains(&neighbor_row) {
+                    Some(grid.row(neighbor_row as usize))
+                } else {
+                    None
+                }
+            },
+            out,
+        );
+    }
+
+    // Same as `accessible` for a row held anywhere: `neighbor_row(dy)` returns
+    // the row `dy` rows away from `cells`, or `None` past the edge of the grid.
+    pub fn classify<'g>(
+        &mut self,
+        width: usize,
+        cells: &[u64],
+        neighbor_row: impl Fn(i64) -> Option<&'g [u64]>,
+        out: &mut [u64],
+    ) {
+        if width == 0 {
+            return;
+        }
+        self.planes
+            .iter_mut()
+            .for_each(|plane| plane.iter_mut().for_each(|word| *word = 0));
+
+        for &(dx, dy, weight) in &self.rules.offsets {
+            let Some(src) = neighbor_row(dy) else {
+                continue;
+            };
+
+            if self.rules.toroidal {
+                // cells shifted past the end come back from the start.
+                let dx = dx.rem_euclid(width as i64);
+                shift_into(src, dx, &mut self.shifted);
+                shift_into(src, dx - width as i64, &mut self.wrapped);
+                self.shifted
+                    .iter_mut()
+                    .zip(&self.wrapped)
+                    .for_each(|(word, wrapped)| *word |= wrapped);
+            } else {
+                shift_into(src, dx, &mut self.shifted);
+            }
+            add_weighted(&mut self.planes, &self.shifted, weight);
+        }
+
+        let threshold = self.rules.threshold as u64;
+        for (idx, out_word) in out.iter_mut().enumerate() {
+            // walk the counter bits from the top, tracking lanes still equal to
+            // the threshold and lanes already known to be below it.
+            let mut below = 0u64;
+            let mut equal = !0u64;
+            for (bit, plane) in self.planes.iter().enumerate().rev() {
+                if threshold >> bit & 1 == 1 {
+                    below |= equal & !plane[idx];
+                    equal &= plane[idx];
+                } else {
+                    equal &= !plane[idx];
+                }
+            }
+            *out_word = below & cells[idx];
+        }
+    }
+}
+
+// Adds `weight` times each bit of `values` to the bit-sliced counters.
+fn add_weighted(planes: &mut [Vec<u64>], values: &[u64], weight: u32) {
+    for weight_bit in 0..32 {
+        if weight >> weight_bit & 1 == 0 {
+            continue;
+        }
+        for (idx, &value) in values.iter().enumerate() {
+            let mut carry = value;
+            for plane in planes[weight_bit as usize..].iter_mut() {
+                if carry == 0 {
+                    break;
+                }
+                let next_carry = plane[idx] & carry;
+                plane[idx] ^= carry;
+                carry = next_carry;
+            }
+        }
+    }
+}
+
+// Same passes as `remove_rolls_by_passes`, classifying 64 cells per step.
+// `on_pass` sees the rolls each pass removes as a mask over the grid, so the
+// cell indices are only worked out by callers that need them.
+pub fn remove_rolls_by_bitset(
+    grid: &mut BitGrid,
+    rules: &Rules,
+    mut on_pass: impl FnMut(&BitGrid),
+) -> Vec<u64> {
+    let mut removed_per_pass = Vec::new();
+    if grid.width == 0 {
+        return removed_per_pass;
+    }
+
+    let mut classifier = RowClassifier::new(rules, grid.words_per_row);
+    let mut removal = BitGrid::new(grid.width, grid.height);
+    loop {
+        for row in 0..grid.height {
+            classifier.accessible(grid, row, removal.row_mut(row));
+        }
+        let removed = removal.count_ones();
+        if removed == 0 {
+            break;
+        }
+        on_pass(&removal);
+        grid.words
+            .iter_mut()
+            .zip(&removal.words)
+            .for_each(|(word, removed)| *word &= !removed);
+        removed_per_pass.push(removed);
+    }
+    removed_per_pass
+}
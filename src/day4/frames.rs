@@ -0,0 +1,357 @@
+use anyhow::{ensure, Context, Result};
+use shared::crc32;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FrameFormat {
+    /// One binary PPM image per frame, in a directory
+    Ppm,
+    /// One PNG image per frame, in a directory
+    Png,
+    /// A single looping animated GIF
+    Gif,
+}
+
+// Palette indices of the cells in a frame.
+pub const EMPTY: u8 = 0;
+pub const ROLL: u8 = 1;
+pub const REMOVED: u8 = 2;
+// GIF color tables come in powers of two, so the last entry is padding.
+pub const PALETTE: [[u8; 3]; 4] = [[255, 255, 255], [90, 90, 90], [220, 40, 40], [0, 0, 0]];
+
+// Frame delays for the GIF, in hundredths of a second.
+const FRAME_DELAY: u16 = 25;
+const LAST_FRAME_DELAY: u16 = 200;
+
+// Draws the grid once before any removal, once per removal pass with the rolls
+// removed in that pass highlighted, and once more with the rolls that are left.
+pub struct FrameWriter {
+    path: PathBuf,
+    format: FrameFormat,
+    width: usize,
+    height: usize,
+    scale: usize,
+    cells: Vec<u8>,
+    last_removed: Vec<usize>,
+    frames: usize,
+    gif: Option<BufWriter<File>>,
+}
+
+impl FrameWriter {
+    pub fn new(
+        path: &Path,
+        format: FrameFormat,
+        scale: usize,
+        width: usize,
+        height: usize,
+        is_roll: impl Fn(usize) -> bool,
+    ) -> Result<Self> {
+        ensure!(scale > 0, "Frame scale has to be at least 1");
+        ensure!(
+            width > 0 && height > 0,
+            "Can't draw frames of an empty grid"
+        );
+        let image_size = (width.checked_mul(scale), height.checked_mul(scale));
+        if format == FrameFormat::Gif {
+            ensure!(
+                matches!(image_size, (Some(w), Some(h)) if w <= u16::MAX as usize && h <= u16::MAX as usize),
+                "GIF frames can be at most {} pixels wide and high",
+                u16::MAX
+            );
+        } else {
+            ensure!(
+                matches!(image_size, (Some(w), Some(h)) if w <= u32::MAX as usize && h <= u32::MAX as usize),
+                "Frames can be at most {} pixels wide and high",
+                u32::MAX
+            );
+        }
+
+        let gif = match format {
+            FrameFormat::Gif => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("Unable to create {}", parent.display()))?;
+                }
+                let mut output = BufWriter::new(
+                    File::create(path)
+                        .with_context(|| format!("Unable to create {}", path.display()))?,
+                );
+                write_gif_header(&mut output, width * scale, height * scale)?;
+                Some(output)
+            }
+            _ => {
+                fs::create_dir_all(path)
+                    .with_context(|| format!("Unable to create {}", path.display()))?;
+                None
+            }
+        };
+
+        let mut frame_writer = FrameWriter {
+            path: path.to_path_buf(),
+            format,
+            width,
+            height,
+            scale,
+            cells: (0..width * height)
+                .map(|cell| if is_roll(cell) { ROLL } else { EMPTY })
+                .collect(),
+            last_removed: Vec::new(),
+            frames: 0,
+            gif,
+        };
+        frame_writer.write_frame(FRAME_DELAY)?;
+        Ok(frame_writer)
+    }
+
+    // Draws a removal pass: the rolls removed in the previous pass are gone and
+    // the ones in `removed` are highlighted.
+    pub fn pass(&mut self, removed: &[usize]) -> Result<()> {
+        self.clear_removed();
+        removed.iter().for_each(|&cell| self.cells[cell] = REMOVED);
+        self.last_removed = removed.to_vec();
+        self.write_frame(FRAME_DELAY)
+    }
+
+    // Draws the rolls that are left and completes the GIF, if any.
+    pub fn finish(mut self) -> Result<usize> {
+        self.clear_removed();
+        self.write_frame(LAST_FRAME_DELAY)?;
+        if let Some(mut output) = self.gif.take() {
+            output.write_all(&[0x3B])?;
+            output.flush()?;
+        }
+        Ok(self.frames)
+    }
+
+    fn clear_removed(&mut self) {
+        for &cell in &self.last_removed {
+            self.cells[cell] = EMPTY;
+        }
+    }
+
+    // Palette indices of every pixel, each cell drawn as a `scale` square.
+    fn pixels(&self) -> Vec<u8> {
+        let image_width = self.width * self.scale;
+        let mut pixels = Vec::with_capacity(image_width * self.height * self.scale);
+        for row in self.cells.chunks(self.width) {
+            let line: Vec<u8> = row
+                .iter()
+                .flat_map(|&cell| std::iter::repeat_n(cell, self.scale))
+                .collect();
+            for _ in 0..self.scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+        pixels
+    }
+
+    fn write_frame(&mut self, delay: u16) -> Result<()> {
+        let pixels = self.pixels();
+        let (width, height) = (self.width * self.scale, self.height * self.scale);
+        let frame_path = |extension: &str| {
+            self.path
+                .join(format!("frame-{:04}.{extension}", self.frames))
+        };
+        match self.format {
+            FrameFormat::Ppm => write_ppm(&frame_path("ppm"), width, height, &pixels)?,
+            FrameFormat::Png => write_png(&frame_path("png"), width, height, &pixels)?,
+            FrameFormat::Gif => {
+                let output = self.gif.as_mut().unwrap();
+                write_gif_frame(output, width, height, &pixels, delay)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+fn write_ppm(path: &Path, width: usize, height: usize, pixels: &[u8]) -> Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    write!(output, "P6\n{width} {height}\n255\n")?;
+    for &pixel in pixels {
+        output.write_all(&PALETTE[pixel as usize])?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+pub fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_png_chunk(output: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    output.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut checked = kind.to_vec();
+    checked.extend_from_slice(data);
+    output.write_all(&checked)?;
+    output.write_all(&crc32(&checked).to_be_bytes())?;
+    Ok(())
+}
+
+// An 8-bit palette PNG. The image data goes into zlib "stored" blocks, which
+// skips compression entirely but keeps the encoder tiny.
+fn write_png(path: &Path, width: usize, height: usize, pixels: &[u8]) -> Result<()> {
+    // every scanline starts with filter type 0 (none).
+    let mut scanlines = Vec::with_capacity((width + 1) * height);
+    for line in pixels.chunks(width) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(line);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = scanlines.chunks(u16::MAX as usize).collect();
+    for (idx, block) in blocks.iter().enumerate() {
+        zlib.push(u8::from(idx + 1 == blocks.len()));
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&scanlines).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth 8, color type 3 (palette), default compression, filter and no
+    // interlacing.
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+
+    let mut output = BufWriter::new(File::create(path)?);
+    output.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_png_chunk(&mut output, b"IHDR", &header)?;
+    write_png_chunk(&mut output, b"PLTE", &PALETTE[..3].concat())?;
+    write_png_chunk(&mut output, b"IDAT", &zlib)?;
+    write_png_chunk(&mut output, b"IEND", &[])?;
+    output.flush()?;
+    Ok(())
+}
+
+// GIF LZW codes start at 3 bits for the 4 color palette.
+const GIF_MIN_CODE_SIZE: u32 = 2;
+
+fn write_gif_header(output: &mut impl Write, width: usize, height: usize) -> Result<()> {
+    output.write_all(b"GIF89a")?;
+    output.write_all(&(width as u16).to_le_bytes())?;
+    output.write_all(&(height as u16).to_le_bytes())?;
+    // global color table of 2^(1 + 1) colors, 2 bits of color resolution.
+    output.write_all(&[0x80 | (1 << 4) | 1, 0, 0])?;
+    output.write_all(&PALETTE.concat())?;
+    // loop forever
+    output.write_all(&[0x21, 0xFF, 0x0B])?;
+    output.write_all(b"NETSCAPE2.0")?;
+    output.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+    Ok(())
+}
+
+fn write_gif_frame(
+    output: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    delay: u16,
+) -> Result<()> {
+    // graphic control extension with the frame delay
+    output.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+    output.write_all(&delay.to_le_bytes())?;
+    output.write_all(&[0x00, 0x00])?;
+
+    // image descriptor covering the whole screen
+    output.write_all(&[0x2C, 0, 0, 0, 0])?;
+    output.write_all(&(width as u16).to_le_bytes())?;
+    output.write_all(&(height as u16).to_le_bytes())?;
+    output.write_all(&[0x00])?;
+
+    output.write_all(&[GIF_MIN_CODE_SIZE as u8])?;
+    for block in lzw_encode(pixels).chunks(255) {
+        output.write_all(&[block.len() as u8])?;
+        output.write_all(block)?;
+    }
+    output.write_all(&[0x00])?;
+    Ok(())
+}
+
+// Packs variable-width codes least significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+// GIF flavored LZW: codes grow from 3 to 12 bits, and the table is cleared
+// once all 4096 codes are taken.
+fn lzw_encode(pixels: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << GIF_MIN_CODE_SIZE;
+    let end = clear + 1;
+    let mut writer = BitWriter {
+        bytes: Vec::new(),
+        buffer: 0,
+        bits: 0,
+    };
+
+    // children[code][pixel] is the code for `code`'s string plus `pixel`, 0 if
+    // there is none yet (no string ever gets code 0 past the single pixels).
+    let reset = |children: &mut Vec<[u16; 4]>| {
+        children.clear();
+        children.resize(end as usize + 1, [0; 4]);
+    };
+    let mut children = Vec::new();
+    reset(&mut children);
+    let mut code_size = GIF_MIN_CODE_SIZE + 1;
+    writer.write(clear, code_size);
+
+    let Some((&first, rest)) = pixels.split_first() else {
+        writer.write(end, code_size);
+        return writer.finish();
+    };
+    let mut prefix = first as u16;
+    for &pixel in rest {
+        let child = children[prefix as usize][pixel as usize];
+        if child != 0 {
+            prefix = child;
+            continue;
+        }
+
+        writer.write(prefix, code_size);
+        let next_code = children.len() as u16;
+        if next_code < 4096 {
+            if next_code == 1 << code_size {
+                code_size += 1;
+            }
+            children[prefix as usize][pixel as usize] = next_code;
+            children.push([0; 4]);
+        } else {
+            writer.write(clear, code_size);
+            reset(&mut children);
+            code_size = GIF_MIN_CODE_SIZE + 1;
+        }
+        prefix = pixel as u16;
+    }
+    writer.write(prefix, code_size);
+    writer.write(end, code_size);
+    writer.finish()
+}
@@ -43,139 +43,223 @@ Consider your complete diagram of the paper roll locations. How many rolls of pa
 
 */
 
-use anyhow::Result;
-use shared::shared_main;
+mod bitgrid;
+mod frames;
+mod rules;
+mod stream;
+
+use anyhow::{ensure, Context, Result};
+use bitgrid::BitGrid;
+use frames::{FrameFormat, FrameWriter};
+use rules::{Neighborhood, Rules};
+use shared::shared_main_with_options;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
-use std::{thread, u64};
+use std::thread;
 use tracing::{debug, info, trace};
 
 fn main() -> Result<()> {
-    shared_main(solution1, solution2)
+    shared_main_with_options(solution1, solution2)
 }
 
-fn solution1(input: &File) -> Result<u64> {
-    let solution: u64 = 0;
-
-    let mut input_reader: BufReader<&File> = BufReader::new(input);
-    let mut x: usize = 0;
-    let mut y: usize = 0;
-    // process the input file, getting the grid bounds.
-    loop {
-        // Read until a delimiter
-        let mut range_bytes = Vec::new();
-        let delim = b'\n';
-        let bytes = input_reader.read_until(delim, &mut range_bytes)?;
-        if bytes == 0 {
-            break;
-        }
-
-        if range_bytes.last() == Some(&delim) {
-            range_bytes.pop();
-        }
+#[derive(Debug, clap::Args)]
+struct Options {
+    /// How accessible rolls are found and repeatedly removed. Part 1 streams the
+    /// input through a few rows at a time only with `bitset`; the others read
+    /// the whole grid into memory first
+    #[arg(long, value_enum, default_value_t = Algorithm::Worklist)]
+    algorithm: Algorithm,
+    /// Threads scanning the grid in part 1 and in each pass of `--algorithm passes`
+    #[arg(long, default_value_t = 32, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    threads: usize,
+    /// Write the pass each roll is removed in to this file as a text map
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+    /// Write the pass each roll is removed in to this file as CSV
+    #[arg(long)]
+    heatmap_csv: Option<PathBuf>,
+    /// Draw the grid before, during and after the removal passes: a directory
+    /// of images, or the animation file for `--frame-format gif`
+    #[arg(long)]
+    frames: Option<PathBuf>,
+    /// Image format of the frames
+    #[arg(long, value_enum, default_value_t = FrameFormat::Png)]
+    frame_format: FrameFormat,
+    /// Width and height in pixels of a cell in the frames
+    #[arg(long, default_value_t = 4)]
+    frame_scale: usize,
+    /// Cells around a roll that count as its neighbors
+    #[arg(long, value_enum, default_value_t = Neighborhood::Moore)]
+    neighborhood: Neighborhood,
+    /// How many cells the neighborhood reaches out from the roll
+    #[arg(long, default_value_t = 1)]
+    radius: u32,
+    /// Weighted neighborhood instead, as rows of weights centered on the roll
+    /// (e.g. `1,2,1;2,0,2;1,2,1`)
+    #[arg(long, conflicts_with_all = ["neighborhood", "radius"])]
+    kernel: Option<String>,
+    /// A roll is accessible when its (weighted) neighbors add up to less than this
+    #[arg(long, default_value_t = 4)]
+    threshold: u32,
+    /// Wrap neighborhoods around the edges of the grid
+    #[arg(long)]
+    toroidal: bool,
+    /// Keep the part 2 grid bit-packed in files in this directory instead of in
+    /// memory, for grids larger than RAM (needs `--algorithm bitset`)
+    #[arg(long)]
+    work_dir: Option<PathBuf>,
+}
 
-        let range = String::from_utf8(range_bytes)?;
+// Whether a heatmap of the pass each roll is removed in is asked for.
+fn wants_heatmap(options: &Options) -> bool {
+    options.heatmap.is_some() || options.heatmap_csv.is_some()
+}
 
-        x = range.len();
-        y += 1;
-    }
+fn build_rules(options: &Options) -> Result<Rules> {
+    let rules = match &options.kernel {
+        Some(kernel) => Rules::from_kernel(kernel, options.threshold, options.toroidal)?,
+        None => Rules::from_neighborhood(
+            options.neighborhood,
+            options.radius,
+            options.threshold,
+            options.toroidal,
+        ),
+    };
+    debug!("Using rules: {:?}", rules);
+    Ok(rules)
+}
 
-    info!("Input grid has width: {x}, height: {y}");
-    // now that we know the bounds, allocate our grid representation
-    let mut grid = Vec::<u8>::with_capacity(x * y);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Algorithm {
+    /// Keep neighbor counts and only re-check the neighbors of removed rolls
+    Worklist,
+    /// Rescan the whole grid on every pass
+    Passes,
+    /// Rescan a grid stored as one bit per cell on every pass, 64 cells at a time
+    Bitset,
+}
 
-    let mut line = 0;
-    // re-read the file, storing the state each row into our grid
-    input_reader.seek(SeekFrom::Start(0))?;
-    loop {
-        // Read until a delimiter
-        let mut range_bytes = Vec::new();
-        let delim = b'\n';
-        let bytes = input_reader.read_until(delim, &mut range_bytes)?;
-        if bytes == 0 {
-            break;
-        }
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
+}
 
-        if range_bytes.last() == Some(&delim) {
-            range_bytes.pop();
+// Reads the diagram into one byte per cell. Every row has to be as wide as the
+// first.
+fn read_grid(input: impl BufRead) -> Result<Grid> {
+    let mut x: usize = 0;
+    let mut y: usize = 0;
+    let mut grid = Vec::<u8>::new();
+    // process the input file, storing the state of each row into our grid.
+    bitgrid::for_each_line(input, |row, line| {
+        if row == 0 {
+            x = line.len();
         }
+        // 1 = roll of paper, 0 = no paper
+        let words = bitgrid::parse_row(line, x, row)?;
+        grid.extend((0..x).map(|col| (words[col / 64] >> (col % 64) & 1) as u8));
+        y += 1;
+        Ok(())
+    })?;
 
-        let range = String::from_utf8(range_bytes)?;
-
-        let row: Vec<u8> = range
-            .chars()
-            .map(|c| match c {
-                '@' => 1u8, // 1 = roll of paper
-                '.' => 0u8, // 0 = no paper
-                c => panic!("Invalid input cell value: {c}"),
-            })
-            .collect();
-        grid.extend_from_slice(&row);
+    info!("Input grid has width: {x}, height: {y}");
+    debug!("Input grid: {:?}", grid);
+    Ok(Grid {
+        width: x,
+        height: y,
+        cells: grid,
+    })
+}
 
-        line += 1;
+fn solution1(input: &File, options: &Options) -> Result<u64> {
+    let rules = build_rules(options)?;
+    if options.algorithm == Algorithm::Bitset {
+        // only a few rows around the current one are ever held in memory.
+        let solution = stream::count_accessible(BufReader::new(input), &rules)?;
+        info!("Number of accessible rolls of paper: {solution}");
+        return Ok(solution);
     }
 
-    info!("Input grid: {:?}", grid);
+    let grid = read_grid(BufReader::new(input))?;
+    let solution = count_accessible(&grid, &rules, options.threads);
+    info!("Number of accessible rolls of paper: {solution}");
+    Ok(solution)
+}
 
-    // For each element in the input grid, compute if there are 4 or more adjacent
-    // rolls to the current roll.
-    let arc_grid: Arc<Vec<u8>> = Arc::<Vec<u8>>::new(grid);
+// Splits `height` rows into at most `threads` contiguous bands, as even as they
+// can be, so that each thread gets a band. No band is empty, so there are fewer
+// bands than threads on short grids and none at all on a grid without rows.
+fn row_bands(height: usize, threads: usize) -> Vec<Range<usize>> {
+    let num_bands = threads.min(height);
+    let mut bands = Vec::with_capacity(num_bands);
+    let mut start_row = 0;
+    for band in 0..num_bands {
+        // the first `height % num_bands` bands take one extra row.
+        let num_rows = height / num_bands + usize::from(band < height % num_bands);
+        bands.push(start_row..start_row + num_rows);
+        start_row += num_rows;
+    }
+    bands
+}
 
-    let sum = Arc::new(AtomicU64::new(0));
-    let num_threads = 32.min(y);
+// Splits `cells` into one slice per band of rows, in order.
+fn split_bands<'a>(cells: &'a mut [u8], width: usize, bands: &[Range<usize>]) -> Vec<&'a mut [u8]> {
+    let mut rest = cells;
+    bands
+        .iter()
+        .map(|band| {
+            let (chunk, tail) = std::mem::take(&mut rest).split_at_mut(band.len() * width);
+            rest = tail;
+            chunk
+        })
+        .collect()
+}
 
-    let array_2d = |arr: &[u8], width: usize, x: usize, y: usize| -> u8 { arr[y * width + x] };
+// Number of rolls accessible in the grid as it is, counted by `threads` threads
+// that each take a band of rows.
+fn count_accessible(grid: &Grid, rules: &Rules, threads: usize) -> u64 {
+    let (x, y) = (grid.width, grid.height);
 
-    let mut handles = Vec::new();
-    for i in 0..num_threads {
-        let grid = arc_grid.clone();
-        let sum = sum.clone();
-        handles.push(thread::spawn(move || {
-            let mut accessible_rolls: u64 = 0;
-            let num_rows = y.div_ceil(num_threads);
-            let start_row = (num_rows * i).min(y);
-            let end_row = (start_row + num_rows).min(y);
-            debug!("Thread: {i} - Start Row: {start_row}, End Row: {end_row}");
-            for row in start_row as i64..end_row as i64 {
-                for col in 0i64..x as i64 {
-                    // skip any cells that aren't rolls of paper.
-                    if array_2d(&grid, x, col as usize, row as usize) == 0 {
-                        continue;
-                    }
-                    let mut num_rolls = 0;
-                    for u in (col - 1).max(0)..(col + 2).min(x as i64) {
-                        for v in (row - 1).max(0)..(row + 2).min(y as i64) {
-                            if !(u == col && v == row) {
-                                let value = array_2d(&grid, x, u as usize, v as usize);
-                                trace!("Thread: {i} - {col},{row} - Checking neighbor: {u},{v} = {value}");
-                                num_rolls += value;
-                            }
+    // For each element in the input grid, compute if its neighbors block the
+    // forklifts.
+    let sum = AtomicU64::new(0);
+    thread::scope(|scope| {
+        for (i, band) in row_bands(y, threads).into_iter().enumerate() {
+            let sum = &sum;
+            scope.spawn(move || {
+                let mut accessible_rolls: u64 = 0;
+                debug!(
+                    "Thread: {i} - Start Row: {}, End Row: {}",
+                    band.start, band.end
+                );
+                for row in band {
+                    for col in 0..x {
+                        // skip any cells that aren't rolls of paper.
+                        if array_2d(&grid.cells, x, col, row) == 0 {
+                            continue;
+                        }
+                        let num_rolls = rules.count(&grid.cells, x, y, row * x + col);
+                        // after processing the kernel for a given cell, add it to our running count of accessible rolls.
+                        trace!("Thread: {i} - {col},{row} - Num Rolls: {num_rolls}");
+                        if rules.is_accessible(num_rolls) {
+                            debug!("Thread: {i} - Accessible Roll: {col},{row}");
+                            accessible_rolls += 1;
                         }
-                    }
-                    // after processing the 3x3 kernel for a given cell, add it to our running count of accessible rolls.
-                    trace!("Thread: {i} - {col},{row} - Num Rolls: {num_rolls}");
-                    if num_rolls < 4 {
-                        debug!("Thread: {i} - Accessible Roll: {col},{row}");
-                        accessible_rolls += 1;
                     }
                 }
-            }
 
-            info!("Thread: {i} - Number of accessible paper towels: {accessible_rolls}");
-            sum.fetch_add(accessible_rolls, Ordering::Relaxed);
-        }));
-    }
-
-    // wait for all threads to complete
-    for h in handles {
-        h.join().unwrap();
-    }
+                info!("Thread: {i} - Number of accessible paper towels: {accessible_rolls}");
+                sum.fetch_add(accessible_rolls, Ordering::Relaxed);
+            });
+        }
+    }); // wait for all threads to complete
 
-    let solution = sum.load(Ordering::Relaxed);
-    info!("Number of accessible rolls of paper: {solution}");
-    Ok(solution)
+    sum.load(Ordering::Relaxed)
 }
 
 fn array_2d(arr: &[u8], width: usize, x: usize, y: usize) -> u8 {
@@ -185,117 +269,56 @@ fn array_2d_mut(arr: &mut [u8], width: usize, x: usize, y: usize) -> &mut u8 {
     &mut arr[y * width + x]
 }
 
-fn solution2(input: &File) -> Result<u64> {
-    let mut solution: u64 = 0;
-
-    let mut input_reader: BufReader<&File> = BufReader::new(input);
-    let mut x: usize = 0;
-    let mut y: usize = 0;
-    // process the input file, getting the grid bounds.
-    loop {
-        // Read until a delimiter
-        let mut range_bytes = Vec::new();
-        let delim = b'\n';
-        let bytes = input_reader.read_until(delim, &mut range_bytes)?;
-        if bytes == 0 {
-            break;
-        }
-
-        if range_bytes.last() == Some(&delim) {
-            range_bytes.pop();
-        }
-
-        let range = String::from_utf8(range_bytes)?;
-
-        x = range.len();
-        y += 1;
-    }
-
-    info!("Input grid has width: {x}, height: {y}");
-    // now that we know the bounds, allocate our grid representation
-    let mut grid = Vec::<u8>::with_capacity(x * y);
+// Removes accessible rolls pass after pass, rescanning the whole grid each time,
+// until no roll is accessible. `on_pass` sees the cells removed by each pass.
+// Returns how many rolls each pass removed.
+fn remove_rolls_by_passes(
+    grid: &mut Grid,
+    rules: &Rules,
+    threads: usize,
+    mut on_pass: impl FnMut(&[usize]),
+) -> Vec<u64> {
+    let x = grid.width;
+    let y = grid.height;
     let mut mask = vec![1u8; x * y];
 
-    let mut line = 0;
-    // re-read the file, storing the state each row into our grid
-    input_reader.seek(SeekFrom::Start(0))?;
-    loop {
-        // Read until a delimiter
-        let mut range_bytes = Vec::new();
-        let delim = b'\n';
-        let bytes = input_reader.read_until(delim, &mut range_bytes)?;
-        if bytes == 0 {
-            break;
-        }
-
-        if range_bytes.last() == Some(&delim) {
-            range_bytes.pop();
-        }
-
-        let range = String::from_utf8(range_bytes)?;
+    // For each element in the input grid, compute if its neighbors block the
+    // forklifts.
 
-        let row: Vec<u8> = range
-            .chars()
-            .map(|c| match c {
-                '@' => 1u8, // 1 = roll of paper
-                '.' => 0u8, // 0 = no paper
-                c => panic!("Invalid input cell value: {c}"),
-            })
-            .collect();
-        grid.extend_from_slice(&row);
-
-        line += 1;
-    }
-
-    info!("Input grid: {:?}", grid);
-
-    // For each element in the input grid, compute if there are 4 or more adjacent
-    // rolls to the current roll.
-
-    let num_threads = 32.min(y);
-    let num_rows = y.div_ceil(num_threads);
-    let chunk_size = num_rows * x; // split the output mask into disjoint chunks for thread processing.
+    // each thread works on the same band of rows in every pass.
+    let bands = row_bands(y, threads);
 
     let reset_mask = |mask: &mut [u8]| {
         mask.iter_mut().for_each(|ele| *ele = 1u8);
     };
 
-    let compute_roll_remove_mask = |mask: &mut [u8], grid: &[u8]| -> Result<u64> {
+    let compute_roll_remove_mask = |mask: &mut [u8], grid: &[u8]| -> u64 {
         debug!("Computing mask for buffer.");
-        let arc_grid = Arc::new(grid);
-        let removed_rolls = Arc::new(AtomicU64::new(0));
+        let removed_rolls = AtomicU64::new(0);
 
         thread::scope(|scope| {
-            for (tid, mask_chunk) in mask.chunks_mut(chunk_size).enumerate() {
-                let grid = arc_grid.clone();
-                let removed_rolls = removed_rolls.clone();
+            let mask_chunks = split_bands(mask, x, &bands);
+            for (tid, (band, mask_chunk)) in bands.iter().zip(mask_chunks).enumerate() {
+                let removed_rolls = &removed_rolls;
                 scope.spawn(move || {
-                    let start_row = num_rows * tid;
-                    let end_row = (start_row + num_rows).min(y);
-                    debug!("Thread: {tid} - Start Row: {start_row}, End Row: {end_row}");
+                    debug!(
+                        "Thread: {tid} - Start Row: {}, End Row: {}",
+                        band.start, band.end
+                    );
                     let mut part_sum = 0u64;
 
-                    for row in start_row as i64..end_row as i64 {
-                        for col in 0i64..x as i64 {
+                    for row in band.clone() {
+                        for col in 0..x {
                             // skip any cells that aren't rolls of paper.
-                            if array_2d(&grid, x, col as usize, row as usize) == 0 {
+                            if array_2d(grid, x, col, row) == 0 {
                                 continue;
                             }
-                            let mut num_rolls = 0;
-                            for u in (col - 1).max(0)..(col + 2).min(x as i64) {
-                                for v in (row - 1).max(0)..(row + 2).min(y as i64) {
-                                    if !(u == col && v == row) {
-                                        let value = array_2d(&grid, x, u as usize, v as usize);
-                                        trace!("Thread: {tid} - {col},{row} - Checking neighbor: {u},{v} = {value}");
-                                        num_rolls += value;
-                                    }
-                                }
-                            }
-                            // after processing the 3x3 kernel for a given cell, add it to our running count of accessible rolls.
+                            let num_rolls = rules.count(grid, x, y, row * x + col);
+                            // after processing the kernel for a given cell, add it to our running count of accessible rolls.
                             trace!("Thread: {tid} - {col},{row} - Num Rolls: {num_rolls}");
-                            if num_rolls < 4 {
+                            if rules.is_accessible(num_rolls) {
                                 debug!("Thread: {tid} - Accessible Roll: {col},{row}");
-                                *array_2d_mut(mask_chunk, x, col as usize, row as usize - start_row) = 0;
+                                *array_2d_mut(mask_chunk, x, col, row - band.start) = 0;
                                 part_sum += 1;
                             }
                         }
@@ -304,44 +327,1143 @@ fn solution2(input: &File) -> Result<u64> {
                 });
             }
         }); // wait for all threads to complete
-        Ok(removed_rolls.load(Ordering::Relaxed))
+        removed_rolls.load(Ordering::Relaxed)
     };
 
-    let apply_mask = |buffer: &mut [u8], mask: &[u8]| -> Result<()> {
+    let apply_mask = |buffer: &mut [u8], mask: &[u8]| {
         debug!("Applying mask to buffer.");
         thread::scope(|scope| {
-            for (tid, buffer_chunk) in buffer.chunks_mut(chunk_size).enumerate() {
-                let mask = Arc::new(mask);
+            let buffer_chunks = split_bands(buffer, x, &bands);
+            for (tid, (band, buffer_chunk)) in bands.iter().zip(buffer_chunks).enumerate() {
                 scope.spawn(move || {
-                    let start_row = num_rows * tid;
-                    let end_row = (start_row + num_rows).min(y);
-                    debug!("Thread: {tid} - Start Row: {start_row}, End Row: {end_row}");
-                    for row in start_row as i64..end_row as i64 {
-                        for col in 0i64..x as i64 {
+                    debug!(
+                        "Thread: {tid} - Start Row: {}, End Row: {}",
+                        band.start, band.end
+                    );
+                    for row in band.clone() {
+                        for col in 0..x {
                             // Multiply the mask over the buffer to remove the paper roll
-                            *array_2d_mut(
-                                buffer_chunk,
-                                x,
-                                col as usize,
-                                row as usize - start_row,
-                            ) *= array_2d(&mask, x, col as usize, row as usize);
+                            *array_2d_mut(buffer_chunk, x, col, row - band.start) *=
+                                array_2d(mask, x, col, row);
                         }
                     }
                 });
             }
         }); // wait for all threads to complete
-        Ok(())
     };
 
-    let mut current_removed = u64::MAX;
-    while current_removed != 0 {
+    let mut removed_per_pass = Vec::new();
+    loop {
         reset_mask(&mut mask);
-        current_removed = compute_roll_remove_mask(&mut mask, &grid)?;
+        let current_removed = compute_roll_remove_mask(&mut mask, &grid.cells);
+        if current_removed == 0 {
+            break;
+        }
+        let removed: Vec<usize> = (0..mask.len()).filter(|&cell| mask[cell] == 0).collect();
+        on_pass(&removed);
+        apply_mask(&mut grid.cells, &mask);
+        removed_per_pass.push(current_removed);
+    }
+    removed_per_pass
+}
+
+// Same result as `remove_rolls_by_passes`, without rescanning the grid.
+//
+// Keeps the weighted neighbor count of every roll. Removing a roll only lowers
+// the counts of the rolls that see it as a neighbor, so the rolls that become
+// accessible in the next pass are exactly those whose count just dropped below
+// the threshold. Each roll is removed once and each removal touches one cell per
+// kernel offset, so the total work is O(cells) rather than O(passes * cells).
+fn remove_rolls_by_worklist(
+    grid: &mut Grid,
+    rules: &Rules,
+    mut on_pass: impl FnMut(&[usize]),
+) -> Vec<u64> {
+    let width = grid.width;
+    let height = grid.height;
+
+    let mut counts = vec![0u32; grid.cells.len()];
+    let mut pass: Vec<usize> = Vec::new();
+    for (cell, count) in counts.iter_mut().enumerate() {
+        if grid.cells[cell] == 0 {
+            continue;
+        }
+        *count = rules.count(&grid.cells, width, height, cell);
+        if rules.is_accessible(*count) {
+            pass.push(cell);
+        }
+    }
+
+    let mut removed_per_pass = Vec::new();
+    while !pass.is_empty() {
+        // remove the whole pass before updating counts, so a roll only becomes
+        // accessible in the pass after its neighbors are gone, as when rescanning.
+        for &cell in &pass {
+            grid.cells[cell] = 0;
+        }
+        on_pass(&pass);
+
+        let mut next_pass = Vec::new();
+        for &cell in &pass {
+            for (neighbor, weight) in rules.reverse_neighbors(width, height, cell) {
+                if grid.cells[neighbor] == 0 {
+                    continue;
+                }
+                let was_accessible = rules.is_accessible(counts[neighbor]);
+                counts[neighbor] -= weight;
+                // only the removal that crosses the threshold queues the roll.
+                if !was_accessible && rules.is_accessible(counts[neighbor]) {
+                    next_pass.push(neighbor);
+                }
+            }
+        }
+
+        debug!(
+            "Removed {} rolls, {} newly accessible",
+            pass.len(),
+            next_pass.len()
+        );
+        removed_per_pass.push(pass.len() as u64);
+        pass = next_pass;
+    }
+    removed_per_pass
+}
+
+// Symbol for a removal pass in the text heatmap: 1-9, then a-z and A-Z.
+fn pass_symbol(pass: u32) -> char {
+    match pass {
+        1..=9 => (b'0' + pass as u8) as char,
+        10..=35 => (b'a' + (pass - 10) as u8) as char,
+        36..=61 => (b'A' + (pass - 36) as u8) as char,
+        _ => '+',
+    }
+}
+
+// Writes the pass each roll was removed in as a text map. Rolls that are never
+// removed form the stable core and are marked `#`, empty cells stay `.`, and
+// passes past 61 (out of symbols) are marked `+`.
+fn write_heatmap(
+    remaining: &BitGrid,
+    removal_passes: &[u32],
+    mut output: impl Write,
+) -> Result<()> {
+    for row in 0..remaining.height {
+        let line: String = (0..remaining.width)
+            .map(|col| {
+                let cell = row * remaining.width + col;
+                match (remaining.is_set(cell), removal_passes[cell]) {
+                    (true, _) => '#',
+                    (_, 0) => '.',
+                    (_, pass) => pass_symbol(pass),
+                }
+            })
+            .collect();
+        writeln!(output, "{line}")?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+// Writes the pass each roll was removed in as CSV, one grid row per line. Empty
+// cells are left blank and the rolls of the stable core are marked `core`.
+fn write_heatmap_csv(
+    remaining: &BitGrid,
+    removal_passes: &[u32],
+    mut output: impl Write,
+) -> Result<()> {
+    for row in 0..remaining.height {
+        let fields: Vec<String> = (0..remaining.width)
+            .map(|col| {
+                let cell = row * remaining.width + col;
+                match (remaining.is_set(cell), removal_passes[cell]) {
+                    (true, _) => "core".to_string(),
+                    (_, 0) => String::new(),
+                    (_, pass) => pass.to_string(),
+                }
+            })
+            .collect();
+        writeln!(output, "{}", fields.join(","))?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+fn create_output(path: &Path) -> Result<BufWriter<File>> {
+    let output =
+        File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+    Ok(BufWriter::new(output))
+}
+
+// Watches the removal passes: keeps the pass each cell is removed in for the
+// heatmaps and draws the animation frames, when they are asked for.
+struct PassObserver {
+    pass: u32,
+    // pass each cell was removed in, 0 if it never was.
+    removal_passes: Vec<u32>,
+    frames: Option<FrameWriter>,
+    // first error writing a frame, reported once the removal is done.
+    frame_result: Result<()>,
+}
+
+impl PassObserver {
+    fn new(
+        options: &Options,
+        width: usize,
+        height: usize,
+        is_roll: impl Fn(usize) -> bool,
+    ) -> Result<Self> {
+        let track_passes = wants_heatmap(options);
+        let frames = match &options.frames {
+            Some(path) => Some(FrameWriter::new(
+                path,
+                options.frame_format,
+                options.frame_scale,
+                width,
+                height,
+                is_roll,
+            )?),
+            None => None,
+        };
+        Ok(PassObserver {
+            pass: 0,
+            removal_passes: vec![0; if track_passes { width * height } else { 0 }],
+            frames,
+            frame_result: Ok(()),
+        })
+    }
+
+    // Whether heatmaps or frames are asked for, so the passes need watching.
+    fn is_watching(&self) -> bool {
+        !self.removal_passes.is_empty() || self.frames.is_some()
+    }
+
+    fn on_pass(&mut self, removed: &[usize]) {
+        self.pass += 1;
+        if !self.removal_passes.is_empty() {
+            removed
+                .iter()
+                .for_each(|&cell| self.removal_passes[cell] = self.pass);
+        }
+        if let (Some(frames), Ok(())) = (&mut self.frames, &self.frame_result) {
+            self.frame_result = frames.pass(removed);
+        }
+    }
+
+    // Completes the frames and hands back the pass each cell was removed in.
+    fn finish(self, options: &Options) -> Result<Vec<u32>> {
+        self.frame_result?;
+        if let (Some(frames), Some(path)) = (self.frames, &options.frames) {
+            let frame_count = frames.finish()?;
+            info!("Wrote {frame_count} frames to {}", path.display());
+        }
+        Ok(self.removal_passes)
+    }
+}
+
+fn solution2(input: &File, options: &Options) -> Result<u64> {
+    let rules = build_rules(options)?;
+
+    if let Some(work_dir) = &options.work_dir {
+        ensure!(
+            options.algorithm == Algorithm::Bitset,
+            "--work-dir needs --algorithm bitset"
+        );
+        ensure!(
+            options.heatmap.is_none() && options.heatmap_csv.is_none() && options.frames.is_none(),
+            "Heatmaps and frames need the whole grid in memory and can't be written with --work-dir"
+        );
+        let removed_per_pass =
+            stream::remove_rolls_on_disk(BufReader::new(input), &rules, work_dir)?;
+        return Ok(sum_removed(removed_per_pass));
+    }
+
+    let (removed_per_pass, remaining, observer) = if options.algorithm == Algorithm::Bitset {
+        let mut grid = BitGrid::read(BufReader::new(input))?;
+        let mut observer =
+            PassObserver::new(options, grid.width, grid.height, |cell| grid.is_set(cell))?;
+        let removed_per_pass = bitgrid::remove_rolls_by_bitset(&mut grid, &rules, |removal| {
+            if observer.is_watching() {
+                observer.on_pass(&removal.set_cells());
+            }
+        });
+        (removed_per_pass, Some(grid), observer)
+    } else {
+        let mut grid = read_grid(BufReader::new(input))?;
+        let mut observer = PassObserver::new(options, grid.width, grid.height, |cell| {
+            grid.cells[cell] == 1
+        })?;
+        let on_pass = |removed: &[usize]| observer.on_pass(removed);
+        let removed_per_pass = match options.algorithm {
+            Algorithm::Worklist => remove_rolls_by_worklist(&mut grid, &rules, on_pass),
+            _ => remove_rolls_by_passes(&mut grid, &rules, options.threads, on_pass),
+        };
+        // only the heatmaps look at the rolls that are left.
+        let remaining = wants_heatmap(options).then(|| BitGrid::from_grid(&grid));
+        (removed_per_pass, remaining, observer)
+    };
+    let removal_passes = observer.finish(options)?;
+
+    // the rolls left in the grid are the core.
+    if let Some(remaining) = &remaining {
+        if let Some(path) = &options.heatmap {
+            write_heatmap(remaining, &removal_passes, create_output(path)?)?;
+            info!("Wrote removal heatmap to {}", path.display());
+        }
+        if let Some(path) = &options.heatmap_csv {
+            write_heatmap_csv(remaining, &removal_passes, create_output(path)?)?;
+            info!("Wrote removal heatmap CSV to {}", path.display());
+        }
+    }
+
+    Ok(sum_removed(removed_per_pass))
+}
+
+fn sum_removed(removed_per_pass: Vec<u64>) -> u64 {
+    let mut solution: u64 = 0;
+    for current_removed in removed_per_pass {
         solution += current_removed;
-        apply_mask(&mut grid, &mask)?;
         info!("Removed {current_removed} paper rolls. Total: {solution}");
     }
 
     info!("Total removed rolls of paper: {solution}");
-    Ok(solution)
+    solution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use shared::test_util::XorShift;
+    use std::fs;
+    use std::time::Instant;
+
+    // Deterministic grid with roughly `density` percent of the cells holding rolls.
+    fn generate_grid(width: usize, height: usize, density: u64, seed: u64) -> Grid {
+        let mut random = XorShift::new(seed);
+        let cells = (0..width * height)
+            .map(|_| u8::from(random.below(100) < density))
+            .collect();
+        Grid {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    fn grid_text(grid: &Grid) -> String {
+        grid.cells
+            .chunks(grid.width)
+            .map(|row| {
+                let mut line: String = row
+                    .iter()
+                    .map(|&cell| if cell == 1 { '@' } else { '.' })
+                    .collect();
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+
+    fn rule_sets() -> Vec<Rules> {
+        vec![
+            Rules::default(),
+            Rules::from_neighborhood(Neighborhood::Moore, 1, 4, true),
+            Rules::from_neighborhood(Neighborhood::VonNeumann, 1, 2, false),
+            Rules::from_neighborhood(Neighborhood::VonNeumann, 2, 6, true),
+            Rules::from_neighborhood(Neighborhood::Moore, 2, 13, false),
+            // lopsided, so neighbors and reverse neighbors differ
+            Rules::from_kernel("0,3,0;1,0,0;2,0,1", 4, false).unwrap(),
+            Rules::from_kernel("0,3,0;1,0,0;2,0,1", 4, true).unwrap(),
+        ]
+    }
+
+    #[rstest]
+    #[case("@.@@\n.@..\n")]
+    #[case("@.@@\r\n.@..\r\n")]
+    #[case("@.@@\r\n.@..")]
+    fn grids_read(#[case] input: &str) {
+        let expected = Grid {
+            width: 4,
+            height: 2,
+            cells: vec![1, 0, 1, 1, 0, 1, 0, 0],
+        };
+        assert_eq!(read_grid(input.as_bytes()).unwrap(), expected);
+        assert_eq!(BitGrid::read(input.as_bytes()).unwrap().to_grid(), expected);
+    }
+
+    #[rstest]
+    #[case("@.@@\n.@..\n@.\n@@@@\n", "Row 3 has width 2, expected 4")]
+    #[case("@.@@\n.@...\n", "Row 2 has width 5, expected 4")]
+    #[case("@.@@\n.@..\n\n", "Row 3 has width 0, expected 4")]
+    #[case("@.@@\n.@x.\n", "Row 2, column 3: invalid cell value 'x'")]
+    #[case("@.@@\n.@ .\n", "Row 2, column 3: invalid cell value ' '")]
+    #[case("@.@@\r\n.@\r.\r\n", "Row 2, column 3: invalid cell value '\\r'")]
+    fn bad_grids(#[case] input: &str, #[case] message: &str) {
+        assert_eq!(
+            read_grid(input.as_bytes()).unwrap_err().to_string(),
+            message
+        );
+        assert_eq!(
+            BitGrid::read(input.as_bytes()).unwrap_err().to_string(),
+            message
+        );
+        assert_eq!(
+            stream::count_accessible(input.as_bytes(), &Rules::default())
+                .unwrap_err()
+                .to_string(),
+            message
+        );
+    }
+
+    const EXAMPLE: &str = "\
+..@@.@@@@.
+@@@.@.@.@@
+@@@@@.@.@@
+@.@@@@..@.
+@@.@@@@.@@
+.@@@@@@@.@
+.@.@.@.@@@
+@.@@@.@@@@
+.@@@@@@@@.
+@.@.@@@.@.
+";
+
+    // The example's heatmap. Pass 1 takes the 13 rolls the puzzle marks as
+    // accessible, and the `#` core is the grid the puzzle is left with.
+    const EXAMPLE_HEATMAP: &str = "\
+..11.1121.
+134.2.2.32
+24578.1.33
+2.69##..2.
+13.####.21
+.24#####.2
+.2.#.#.##3
+1.4##.###4
+.23#####5.
+1.1.###.1.
+";
+
+    // Removes rolls from the example with each algorithm, returning the grid
+    // that is left and the pass each cell was removed in.
+    fn example_removal(algorithm: Algorithm) -> (BitGrid, Vec<u32>) {
+        let rules = Rules::default();
+        let mut grid = read_grid(EXAMPLE.as_bytes()).unwrap();
+        let mut removal_passes = vec![0; grid.cells.len()];
+        let mut pass = 0;
+        let mut on_pass = |removed: &[usize]| {
+            pass += 1;
+            removed.iter().for_each(|&cell| removal_passes[cell] = pass);
+        };
+        let remaining = match algorithm {
+            Algorithm::Worklist => {
+                remove_rolls_by_worklist(&mut grid, &rules, &mut on_pass);
+                BitGrid::from_grid(&grid)
+            }
+            Algorithm::Passes => {
+                remove_rolls_by_passes(&mut grid, &rules, 3, &mut on_pass);
+                BitGrid::from_grid(&grid)
+            }
+            Algorithm::Bitset => {
+                let mut bit_grid = BitGrid::from_grid(&grid);
+                bitgrid::remove_rolls_by_bitset(&mut bit_grid, &rules, |removal| {
+                    on_pass(&removal.set_cells())
+                });
+                bit_grid
+            }
+        };
+        (remaining, removal_passes)
+    }
+
+    #[rstest]
+    fn example_heatmap(
+        #[values(Algorithm::Worklist, Algorithm::Passes, Algorithm::Bitset)] algorithm: Algorithm,
+    ) {
+        let (remaining, removal_passes) = example_removal(algorithm);
+        // a few known cells: (row, col) and the pass they go in, 0 for empty.
+        let cell = |row: usize, col: usize| removal_passes[row * 10 + col];
+        assert_eq!(cell(0, 2), 1);
+        assert_eq!(cell(0, 7), 2);
+        assert_eq!(cell(2, 4), 8);
+        assert_eq!(cell(3, 3), 9);
+        assert_eq!(cell(0, 0), 0);
+        assert!(remaining.is_set(3 * 10 + 4));
+        assert_eq!(removal_passes.iter().filter(|&&pass| pass > 0).count(), 43);
+
+        let mut heatmap = Vec::new();
+        write_heatmap(&remaining, &removal_passes, &mut heatmap).unwrap();
+        assert_eq!(String::from_utf8(heatmap).unwrap(), EXAMPLE_HEATMAP);
+    }
+
+    #[test]
+    fn example_heatmap_csv() {
+        let (remaining, removal_passes) = example_removal(Algorithm::Worklist);
+        let mut csv = Vec::new();
+        write_heatmap_csv(&remaining, &removal_passes, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), 10);
+        assert!(rows.iter().all(|row| row.len() == 10));
+        assert_eq!(rows[0], ["", "", "1", "1", "", "1", "1", "2", "1", ""]);
+        assert_eq!(
+            rows[3],
+            ["2", "", "6", "9", "core", "core", "", "", "2", ""]
+        );
+        // the CSV holds the same passes as the text map.
+        for (line, csv_row) in EXAMPLE_HEATMAP.lines().zip(&rows) {
+            for (symbol, field) in line.chars().zip(csv_row) {
+                let expected = match symbol {
+                    '#' => "core".to_string(),
+                    '.' => String::new(),
+                    pass => pass.to_string(),
+                };
+                assert_eq!(*field, expected);
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(1, '1')]
+    #[case(9, '9')]
+    #[case(10, 'a')]
+    #[case(35, 'z')]
+    #[case(36, 'A')]
+    #[case(61, 'Z')]
+    #[case(62, '+')]
+    fn pass_symbols(#[case] pass: u32, #[case] symbol: char) {
+        assert_eq!(pass_symbol(pass), symbol);
+    }
+
+    #[rstest]
+    #[case(Neighborhood::Moore, 1, 8)]
+    #[case(Neighborhood::Moore, 2, 24)]
+    #[case(Neighborhood::VonNeumann, 1, 4)]
+    #[case(Neighborhood::VonNeumann, 2, 12)]
+    fn neighborhood_sizes(
+        #[case] neighborhood: Neighborhood,
+        #[case] radius: u32,
+        #[case] size: usize,
+    ) {
+        let rules = Rules::from_neighborhood(neighborhood, radius, 4, false);
+        assert_eq!(rules.offsets.len(), size);
+        assert!(rules.offsets.iter().all(|&(_, _, weight)| weight == 1));
+    }
+
+    #[rstest]
+    // neighbor counts of a corner, an edge and the center of a full 3x3 grid
+    #[case(Rules::default(), [3, 5, 8])]
+    #[case(Rules::from_neighborhood(Neighborhood::VonNeumann, 1, 4, false), [2, 3, 4])]
+    #[case(Rules::from_kernel("1,2,1;2,0,2;1,2,1", 4, false).unwrap(), [5, 8, 12])]
+    // wrapping around, every cell sees the whole grid but itself
+    #[case(Rules::from_neighborhood(Neighborhood::Moore, 1, 4, true), [8, 8, 8])]
+    #[case(Rules::from_neighborhood(Neighborhood::VonNeumann, 1, 4, true), [4, 4, 4])]
+    // a radius past the grid size wraps onto the same cells more than once
+    #[case(Rules::from_neighborhood(Neighborhood::Moore, 2, 4, true), [24, 24, 24])]
+    fn full_grid_counts(#[case] rules: Rules, #[case] counts: [u32; 3]) {
+        let cells = vec![1; 9];
+        assert_eq!(
+            [0, 1, 4].map(|cell| rules.count(&cells, 3, 3, cell)),
+            counts
+        );
+    }
+
+    #[rstest]
+    // all 71 rolls have fewer than 9 neighbors, and none has fewer than 0
+    #[case(9, vec![71])]
+    #[case(0, vec![])]
+    // the puzzle's own threshold
+    #[case(4, vec![13, 12, 7, 5, 2, 1, 1, 1, 1])]
+    fn example_thresholds(#[case] threshold: u32, #[case] removed_per_pass: Vec<u64>) {
+        let rules = Rules::from_neighborhood(Neighborhood::Moore, 1, threshold, false);
+        let mut grid = read_grid(EXAMPLE.as_bytes()).unwrap();
+        assert_eq!(
+            remove_rolls_by_worklist(&mut grid, &rules, |_| {}),
+            removed_per_pass
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "1,2;3,4",
+        "Kernel has to be a square with an odd number of rows, got: 1,2;3,4"
+    )]
+    #[case(
+        "1,1,1;1,0,1",
+        "Kernel has to be a square with an odd number of rows, got: 1,1,1;1,0,1"
+    )]
+    #[case(
+        "1,1,1;1,1,1;1,1,1",
+        "Kernel center is the roll itself and has to be 0"
+    )]
+    #[case("1,x,1;1,0,1;1,1,1", "Invalid kernel weight: \"x\"")]
+    fn bad_kernels(#[case] kernel: &str, #[case] message: &str) {
+        let error = Rules::from_kernel(kernel, 4, false).unwrap_err();
+        assert_eq!(error.to_string(), message);
+    }
+
+    // Cells of `row` marked in a packed row of accessible rolls.
+    fn accessible_cells(width: usize, row: usize, accessible: &[u64]) -> Vec<usize> {
+        (0..width)
+            .filter(|&col| accessible[col / 64] >> (col % 64) & 1 == 1)
+            .map(|col| row * width + col)
+            .collect()
+    }
+
+    #[test]
+    fn row_window_streams_rows() {
+        for rules in rule_sets() {
+            let reach = rules.reach();
+            for (seed, (width, height)) in [(70, 12), (5, 3), (64, 1)].into_iter().enumerate() {
+                let grid = generate_grid(width, height, 60, seed as u64 + 1);
+                let mut window = stream::RowWindow::new(&rules, width);
+                let mut classified: Vec<(usize, Vec<usize>)> = Vec::new();
+                for (pushed, line) in grid_text(&grid).lines().enumerate() {
+                    let cells = bitgrid::parse_row(line.as_bytes(), width, pushed).unwrap();
+                    window.push(cells, |row, _, accessible| {
+                        classified.push((row, accessible_cells(width, row, accessible)))
+                    });
+                    // a row is classified as soon as the rows it reaches are in,
+                    // except for the top rows of a toroidal grid, which reach the
+                    // bottom ones.
+                    let held_back = if rules.toroidal { 2 * reach } else { reach };
+                    assert_eq!(
+                        classified.len(),
+                        (pushed + 1).saturating_sub(held_back),
+                        "{width}x{height} after row {pushed} with {rules:?}"
+                    );
+                }
+                window.finish(|row, _, accessible| {
+                    classified.push((row, accessible_cells(width, row, accessible)))
+                });
+
+                classified.sort_unstable();
+                let rows: Vec<usize> = classified.iter().map(|(row, _)| *row).collect();
+                assert_eq!(rows, (0..height).collect::<Vec<_>>());
+                let accessible: Vec<usize> = (0..grid.cells.len())
+                    .filter(|&cell| {
+                        grid.cells[cell] == 1
+                            && rules.is_accessible(rules.count(&grid.cells, width, height, cell))
+                    })
+                    .collect();
+                let classified: Vec<usize> = classified
+                    .into_iter()
+                    .flat_map(|(_, cells)| cells)
+                    .collect();
+                assert_eq!(classified, accessible, "{width}x{height} with {rules:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn example_streams() {
+        let rules = Rules::default();
+        assert_eq!(
+            stream::count_accessible(EXAMPLE.as_bytes(), &rules).unwrap(),
+            13
+        );
+        let work_dir =
+            std::env::temp_dir().join(format!("day4-example-test-{}", std::process::id()));
+        fs::create_dir_all(&work_dir).unwrap();
+        assert_eq!(
+            stream::remove_rolls_on_disk(EXAMPLE.as_bytes(), &rules, &work_dir).unwrap(),
+            [13, 12, 7, 5, 2, 1, 1, 1, 1]
+        );
+        fs::remove_dir(&work_dir).unwrap();
+    }
+
+    #[test]
+    fn worklist_matches_passes() {
+        for rules in rule_sets() {
+            for (seed, (width, height)) in [(1, 1), (1, 7), (7, 1), (10, 10), (33, 65), (128, 40)]
+                .into_iter()
+                .enumerate()
+            {
+                for density in [30, 60, 75, 90] {
+                    let grid = generate_grid(width, height, density, seed as u64 + 1);
+                    let mut passes_grid = grid.clone();
+                    let mut worklist_grid = grid;
+                    assert_eq!(
+                        remove_rolls_by_worklist(&mut worklist_grid, &rules, |_| {}),
+                        remove_rolls_by_passes(&mut passes_grid, &rules, 32, |_| {}),
+                        "{width}x{height} at {density}% with {rules:?}"
+                    );
+                    assert_eq!(worklist_grid, passes_grid);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bitset_matches_bytes() {
+        let work_dir = std::env::temp_dir().join(format!("day4-test-{}", std::process::id()));
+        fs::create_dir_all(&work_dir).unwrap();
+        for rules in rule_sets() {
+            // widths around the 64 cell word boundaries
+            for (seed, (width, height)) in [
+                (1, 1),
+                (1, 7),
+                (7, 1),
+                (3, 2),
+                (63, 5),
+                (64, 9),
+                (65, 3),
+                (130, 20),
+                (5, 40),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                for density in [30, 60, 75, 90] {
+                    let grid = generate_grid(width, height, density, seed as u64 + 1);
+                    let text = grid_text(&grid);
+                    let mut bit_grid = BitGrid::from_grid(&grid);
+                    assert_eq!(bit_grid.to_grid(), grid);
+
+                    let accessible = (0..grid.cells.len())
+                        .filter(|&cell| {
+                            grid.cells[cell] == 1
+                                && rules.is_accessible(rules.count(
+                                    &grid.cells,
+                                    width,
+                                    height,
+                                    cell,
+                                ))
+                        })
+                        .count() as u64;
+                    assert_eq!(
+                        stream::count_accessible(text.as_bytes(), &rules).unwrap(),
+                        accessible,
+                        "{width}x{height} at {density}% with {rules:?}"
+                    );
+
+                    let mut passes_grid = grid;
+                    let on_disk_removed =
+                        stream::remove_rolls_on_disk(text.as_bytes(), &rules, &work_dir).unwrap();
+                    let mut bitset_cells = Vec::new();
+                    let mut passes_cells = Vec::new();
+                    assert_eq!(
+                        bitgrid::remove_rolls_by_bitset(&mut bit_grid, &rules, |removal| {
+                            bitset_cells.push(removal.set_cells())
+                        }),
+                        remove_rolls_by_passes(&mut passes_grid, &rules, 32, |removed| {
+                            let mut removed = removed.to_vec();
+                            removed.sort_unstable();
+                            passes_cells.push(removed)
+                        }),
+                        "{width}x{height} at {density}% with {rules:?}"
+                    );
+                    assert_eq!(bitset_cells, passes_cells);
+                    assert_eq!(
+                        on_disk_removed,
+                        passes_cells
+                            .iter()
+                            .map(|cells| cells.len() as u64)
+                            .collect::<Vec<_>>()
+                    );
+                    assert_eq!(bit_grid.to_grid(), passes_grid);
+                }
+            }
+        }
+        // the packed grids clean up after themselves.
+        fs::remove_dir(&work_dir).unwrap();
+    }
+
+    #[test]
+    fn work_files_cleaned_up() {
+        let work_dir =
+            std::env::temp_dir().join(format!("day4-cleanup-test-{}", std::process::id()));
+        fs::create_dir_all(&work_dir).unwrap();
+        let rules = Rules::default();
+
+        // a bad row part way through leaves no half written grid behind.
+        let error = stream::remove_rolls_on_disk("@@@\n@.@\n@x@\n".as_bytes(), &rules, &work_dir)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Row 3, column 2: invalid cell value 'x'");
+        assert_eq!(fs::read_dir(&work_dir).unwrap().count(), 0);
+
+        // runs sharing the work dir each get their own files.
+        let expected = stream::remove_rolls_on_disk(EXAMPLE.as_bytes(), &rules, &work_dir).unwrap();
+        thread::scope(|scope| {
+            let runs: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        stream::remove_rolls_on_disk(EXAMPLE.as_bytes(), &rules, &work_dir).unwrap()
+                    })
+                })
+                .collect();
+            for run in runs {
+                assert_eq!(run.join().unwrap(), expected);
+            }
+        });
+        assert_eq!(expected.iter().sum::<u64>(), 43);
+        fs::remove_dir(&work_dir).unwrap();
+    }
+
+    #[test]
+    fn checksum_vectors() {
+        assert_eq!(shared::crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(shared::crc32(b""), 0);
+        assert_eq!(frames::adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(frames::adler32(b""), 1);
+    }
+
+    // An image as its width, height and palette index per pixel.
+    type Image = (usize, usize, Vec<u8>);
+
+    fn palette_index(rgb: &[u8]) -> u8 {
+        frames::PALETTE
+            .iter()
+            .position(|color| color == rgb)
+            .expect("a palette color") as u8
+    }
+
+    fn decode_ppm(bytes: &[u8]) -> Image {
+        let mut fields = bytes.splitn(5, |&b| b == b'\n' || b == b' ');
+        let mut field = || std::str::from_utf8(fields.next().unwrap()).unwrap();
+        assert_eq!(field(), "P6");
+        let (width, height): (usize, usize) = (field().parse().unwrap(), field().parse().unwrap());
+        assert_eq!(field(), "255");
+        let data = fields.next().unwrap();
+        assert_eq!(data.len(), width * height * 3);
+        (width, height, data.chunks(3).map(palette_index).collect())
+    }
+
+    // Reads a PNG as `write_png` writes it: checking the chunk CRCs, the zlib
+    // stored blocks and their Adler-32, and that no scanline is filtered.
+    fn decode_png(bytes: &[u8]) -> Image {
+        assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+        let be32 = |bytes: &[u8]| u32::from_be_bytes(bytes[..4].try_into().unwrap());
+        let (mut width, mut height) = (0, 0);
+        let mut palette = Vec::new();
+        let mut zlib = Vec::new();
+        let mut rest = &bytes[8..];
+        while !rest.is_empty() {
+            let len = be32(rest) as usize;
+            let (checked, crc) = rest[4..].split_at(4 + len);
+            assert_eq!(shared::crc32(checked), be32(crc));
+            let (kind, data) = checked.split_at(4);
+            match kind {
+                b"IHDR" => {
+                    (width, height) = (be32(data) as usize, be32(&data[4..]) as usize);
+                    assert_eq!(&data[8..], [8, 3, 0, 0, 0]);
+                }
+                b"PLTE" => palette = data.chunks(3).map(palette_index).collect(),
+                b"IDAT" => zlib.extend_from_slice(data),
+                b"IEND" => assert!(data.is_empty()),
+                kind => panic!("unexpected chunk {kind:?}"),
+            }
+            rest = &rest[12 + len..];
+        }
+
+        assert_eq!(zlib[..2], [0x78, 0x01]);
+        let mut scanlines = Vec::new();
+        let mut blocks = &zlib[2..];
+        loop {
+            let last = blocks[0] == 1;
+            let len = u16::from_le_bytes([blocks[1], blocks[2]]);
+            assert_eq!(!len, u16::from_le_bytes([blocks[3], blocks[4]]));
+            scanlines.extend_from_slice(&blocks[5..5 + len as usize]);
+            blocks = &blocks[5 + len as usize..];
+            if last {
+                break;
+            }
+        }
+        assert_eq!(frames::adler32(&scanlines), be32(blocks));
+        assert_eq!(blocks.len(), 4);
+
+        let mut pixels = Vec::new();
+        for line in scanlines.chunks(width + 1) {
+            assert_eq!(line[0], 0);
+            pixels.extend(line[1..].iter().map(|&pixel| palette[pixel as usize]));
+        }
+        assert_eq!(pixels.len(), width * height);
+        (width, height, pixels)
+    }
+
+    fn lzw_decode(min_code_size: u32, data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let singles = || -> Vec<Vec<u8>> { (0..=end).map(|code| vec![code as u8]).collect() };
+        let mut table = singles();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut pixels = Vec::new();
+        let mut bit = 0;
+        loop {
+            let code = (0..code_size as usize).fold(0, |code, idx| {
+                let at = bit + idx;
+                code | usize::from(data[at / 8] >> (at % 8) & 1) << idx
+            });
+            bit += code_size as usize;
+            if code == clear {
+                table = singles();
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return pixels;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                // the code being defined by this very step
+                (None, Some(previous)) if code == table.len() => {
+                    [&previous[..], &previous[..1]].concat()
+                }
+                _ => panic!("undefined LZW code {code}"),
+            };
+            pixels.extend_from_slice(&entry);
+            if let Some(previous) = previous {
+                if table.len() < 4096 {
+                    table.push([&previous[..], &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+            previous = Some(entry);
+        }
+    }
+
+    // Reads the frames of a GIF as `write_gif_frame` writes them, with their
+    // delays.
+    fn decode_gif(bytes: &[u8]) -> Vec<(u16, Image)> {
+        let le16 = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize;
+        assert_eq!(&bytes[..6], b"GIF89a");
+        let (width, height) = (le16(6), le16(8));
+        assert_eq!(bytes[10], 0x91);
+        let palette: Vec<u8> = bytes[13..25].chunks(3).map(palette_index).collect();
+        let sub_blocks = |mut at: usize| -> (Vec<u8>, usize) {
+            let mut data = Vec::new();
+            while bytes[at] != 0 {
+                data.extend_from_slice(&bytes[at + 1..at + 1 + bytes[at] as usize]);
+                at += 1 + bytes[at] as usize;
+            }
+            (data, at + 1)
+        };
+
+        let mut frames = Vec::new();
+        let mut delay = 0;
+        let mut at = 25;
+        loop {
+            match bytes[at] {
+                0x21 => {
+                    let (data, next) = sub_blocks(at + 2);
+                    if bytes[at + 1] == 0xF9 {
+                        delay = u16::from_le_bytes([data[1], data[2]]);
+                    }
+                    at = next;
+                }
+                0x2C => {
+                    assert_eq!((le16(at + 1), le16(at + 3)), (0, 0));
+                    assert_eq!((le16(at + 5), le16(at + 7)), (width, height));
+                    let (data, next) = sub_blocks(at + 11);
+                    let pixels = lzw_decode(bytes[at + 10] as u32, &data);
+                    let pixels = pixels.iter().map(|&pixel| palette[pixel as usize]);
+                    frames.push((delay, (width, height, pixels.collect())));
+                    at = next;
+                }
+                0x3B => {
+                    assert_eq!(at + 1, bytes.len());
+                    return frames;
+                }
+                byte => panic!("unexpected GIF block {byte:#x}"),
+            }
+        }
+    }
+
+    // Writes the frames of a grid whose rolls are removed in `passes`, and reads
+    // them back.
+    fn frame_round_trip(
+        path: &Path,
+        format: FrameFormat,
+        scale: usize,
+        grid: &Grid,
+        passes: &[Vec<usize>],
+    ) -> Vec<Image> {
+        let mut writer = FrameWriter::new(path, format, scale, grid.width, grid.height, |cell| {
+            grid.cells[cell] == 1
+        })
+        .unwrap();
+        for removed in passes {
+            writer.pass(removed).unwrap();
+        }
+        let frame_count = writer.finish().unwrap();
+        assert_eq!(frame_count, passes.len() + 2);
+
+        let frame_path =
+            |extension: &str, frame: usize| path.join(format!("frame-{frame:04}.{extension}"));
+        match format {
+            FrameFormat::Ppm => (0..frame_count)
+                .map(|frame| decode_ppm(&fs::read(frame_path("ppm", frame)).unwrap()))
+                .collect(),
+            FrameFormat::Png => (0..frame_count)
+                .map(|frame| decode_png(&fs::read(frame_path("png", frame)).unwrap()))
+                .collect(),
+            FrameFormat::Gif => {
+                let frames = decode_gif(&fs::read(path).unwrap());
+                let delays: Vec<u16> = frames.iter().map(|(delay, _)| *delay).collect();
+                let mut expected = vec![25; frame_count];
+                expected[frame_count - 1] = 200;
+                assert_eq!(delays, expected);
+                frames.into_iter().map(|(_, image)| image).collect()
+            }
+        }
+    }
+
+    #[rstest]
+    fn frames_round_trip(
+        #[values(FrameFormat::Ppm, FrameFormat::Png, FrameFormat::Gif)] format: FrameFormat,
+    ) {
+        let dir = std::env::temp_dir().join(format!(
+            "day4-frames-test-{}-{format:?}",
+            std::process::id()
+        ));
+        // the GIF goes into a directory that doesn't exist yet, like the images.
+        let path = match format {
+            FrameFormat::Gif => dir.join("nested").join("removal.gif"),
+            _ => dir.join("nested"),
+        };
+
+        // 3x2 rolls, two of them removed in the first pass and one in the second
+        let grid = Grid {
+            width: 3,
+            height: 2,
+            cells: vec![1, 1, 0, 1, 0, 1],
+        };
+        let (e, r, x) = (frames::EMPTY, frames::ROLL, frames::REMOVED);
+        let expected_cells = [
+            [r, r, e, r, e, r],
+            [r, x, e, r, e, x],
+            [x, e, e, r, e, e],
+            [e, e, e, r, e, e],
+        ];
+        let frames = frame_round_trip(&path, format, 2, &grid, &[vec![1, 5], vec![0]]);
+        assert_eq!(frames.len(), expected_cells.len());
+        for (frame, cells) in frames.iter().zip(expected_cells) {
+            // each cell is a 2x2 square
+            let pixels: Vec<u8> = cells
+                .chunks(3)
+                .flat_map(|row| {
+                    let line: Vec<u8> = row.iter().flat_map(|&cell| [cell, cell]).collect();
+                    [line.clone(), line].concat()
+                })
+                .collect();
+            assert_eq!(*frame, (6, 4, pixels));
+        }
+
+        // a grid large enough to fill the GIF code table and the PNG blocks.
+        let grid = generate_grid(300, 250, 60, 7);
+        let mut bit_grid = BitGrid::from_grid(&grid);
+        let mut passes = Vec::new();
+        bitgrid::remove_rolls_by_bitset(&mut bit_grid, &Rules::default(), |removal| {
+            passes.push(removal.set_cells())
+        });
+        let frames = frame_round_trip(&dir.join("large"), format, 1, &grid, &passes[..3]);
+        let mut cells: Vec<u8> = grid.cells.clone();
+        assert_eq!(frames[0], (300, 250, cells.clone()));
+        for (frame, removed) in frames[1..].iter().zip(&passes[..3]) {
+            cells.iter_mut().for_each(|cell| {
+                if *cell == frames::REMOVED {
+                    *cell = frames::EMPTY
+                }
+            });
+            removed
+                .iter()
+                .for_each(|&cell| cells[cell] = frames::REMOVED);
+            assert_eq!(frame.2, cells);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn row_bands_cover_grid() {
+        for height in 0..100 {
+            for threads in 1..=64 {
+                let bands = row_bands(height, threads);
+                assert_eq!(bands.len(), threads.min(height));
+                assert!(bands.iter().all(|band| !band.is_empty()));
+                assert!(bands.windows(2).all(|pair| pair[0].end == pair[1].start));
+                assert_eq!(bands.first().map_or(0, |band| band.start), 0);
+                assert_eq!(bands.last().map_or(0, |band| band.end), height);
+                let sizes: Vec<usize> = bands.iter().map(|band| band.len()).collect();
+                assert!(sizes.iter().max().unwrap_or(&0) - sizes.iter().min().unwrap_or(&0) <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn results_independent_of_threads() {
+        let rules = Rules::default();
+        for (seed, (width, height)) in [
+            (0, 0),
+            (5, 0),
+            (0, 5),
+            (1, 1),
+            (1, 50),
+            (50, 1),
+            (13, 29),
+            (64, 65),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let grid = generate_grid(width, height, 70, seed as u64 + 1);
+            let accessible = (0..grid.cells.len())
+                .filter(|&cell| {
+                    grid.cells[cell] == 1
+                        && rules.is_accessible(rules.count(&grid.cells, width, height, cell))
+                })
+                .count() as u64;
+            let mut worklist_grid = grid.clone();
+            let worklist_removed = remove_rolls_by_worklist(&mut worklist_grid, &rules, |_| {});
+
+            for threads in 1..=64 {
+                assert_eq!(
+                    count_accessible(&grid, &rules, threads),
+                    accessible,
+                    "{width}x{height} with {threads} threads"
+                );
+                let mut passes_grid = grid.clone();
+                assert_eq!(
+                    remove_rolls_by_passes(&mut passes_grid, &rules, threads, |_| {}),
+                    worklist_removed,
+                    "{width}x{height} with {threads} threads"
+                );
+                assert_eq!(passes_grid, worklist_grid);
+            }
+        }
+    }
+
+    // cargo test --release --bin day4 -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmark_worklist_against_passes() {
+        let grid = generate_grid(10_000, 10_000, 75, 2025);
+
+        let mut worklist_grid = grid.clone();
+        let start = Instant::now();
+        let worklist_removed =
+            remove_rolls_by_worklist(&mut worklist_grid, &Rules::default(), |_| {});
+        let worklist_time = start.elapsed();
+
+        let mut passes_grid = grid.clone();
+        let start = Instant::now();
+        let passes_removed =
+            remove_rolls_by_passes(&mut passes_grid, &Rules::default(), 32, |_| {});
+        let passes_time = start.elapsed();
+
+        let mut bit_grid = BitGrid::from_grid(&grid);
+        let start = Instant::now();
+        let bitset_removed =
+            bitgrid::remove_rolls_by_bitset(&mut bit_grid, &Rules::default(), |_| {});
+        let bitset_time = start.elapsed();
+
+        println!(
+            "10000x10000: {} passes, {} rolls removed. worklist: {worklist_time:?}, passes: {passes_time:?}, bitset: {bitset_time:?}",
+            worklist_removed.len(),
+            worklist_removed.iter().sum::<u64>()
+        );
+        assert_eq!(worklist_removed, passes_removed);
+        assert_eq!(bitset_removed, passes_removed);
+    }
 }
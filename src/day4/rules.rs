@@ -0,0 +1,168 @@
+use anyhow::{ensure, Context, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Neighborhood {
+    /// Every cell within the radius, diagonals included
+    Moore,
+    /// Cells within the radius in steps along rows and columns
+    VonNeumann,
+}
+
+// Which cells count towards a roll's neighbors and how much each one weighs. A
+// roll is accessible while its weighted neighbors add up to less than the
+// threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rules {
+    // (column offset, row offset, weight) of each neighbor
+    pub offsets: Vec<(i64, i64, u32)>,
+    pub threshold: u32,
+    // neighbors past an edge wrap around to the opposite edge
+    pub toroidal: bool,
+}
+
+impl Default for Rules {
+    // The puzzle's rules: fewer than 4 rolls in the 8 adjacent cells.
+    fn default() -> Self {
+        Rules::from_neighborhood(Neighborhood::Moore, 1, 4, false)
+    }
+}
+
+impl Rules {
+    pub fn from_neighborhood(
+        neighborhood: Neighborhood,
+        radius: u32,
+        threshold: u32,
+        toroidal: bool,
+    ) -> Self {
+        let radius = radius as i64;
+        let mut offsets = Vec::new();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let in_neighborhood = match neighborhood {
+                    Neighborhood::Moore => true,
+                    Neighborhood::VonNeumann => dx.abs() + dy.abs() <= radius,
+                };
+                if in_neighborhood && (dx, dy) != (0, 0) {
+                    offsets.push((dx, dy, 1));
+                }
+            }
+        }
+        Rules {
+            offsets,
+            threshold,
+            toroidal,
+        }
+    }
+
+    // Parses a square kernel of weights centered on the roll, with rows split by
+    // `;` and columns by `,`, e.g. `1,2,1;2,0,2;1,2,1`. The center is the roll
+    // itself and has to be 0.
+    pub fn from_kernel(kernel: &str, threshold: u32, toroidal: bool) -> Result<Self> {
+        let rows: Vec<Vec<u32>> = kernel
+            .split(';')
+            .map(|row| {
+                row.split(',')
+                    .map(|weight| {
+                        weight
+                            .trim()
+                            .parse::<u32>()
+                            .with_context(|| format!("Invalid kernel weight: {weight:?}"))
+                    })
+                    .collect()
+            })
+            .collect::<Result<_>>()?;
+
+        let size = rows.len();
+        ensure!(
+            size % 2 == 1 && rows.iter().all(|row| row.len() == size),
+            "Kernel has to be a square with an odd number of rows, got: {kernel}"
+        );
+        let radius = (size / 2) as i64;
+        ensure!(
+            rows[size / 2][size / 2] == 0,
+            "Kernel center is the roll itself and has to be 0"
+        );
+
+        let mut offsets = Vec::new();
+        for (row_idx, row) in rows.iter().enumerate() {
+            for (col_idx, &weight) in row.iter().enumerate() {
+                if weight > 0 {
+                    offsets.push((col_idx as i64 - radius, row_idx as i64 - radius, weight));
+                }
+            }
+        }
+        Ok(Rules {
+            offsets,
+            threshold,
+            toroidal,
+        })
+    }
+
+    fn offset_cell(
+        &self,
+        width: usize,
+        height: usize,
+        cell: usize,
+        dx: i64,
+        dy: i64,
+    ) -> Option<usize> {
+        let col = (cell % width) as i64 + dx;
+        let row = (cell / width) as i64 + dy;
+        let (col, row) = if self.toroidal {
+            (col.rem_euclid(width as i64), row.rem_euclid(height as i64))
+        } else if (0..width as i64).contains(&col) && (0..height as i64).contains(&row) {
+            (col, row)
+        } else {
+            return None;
+        };
+        Some(row as usize * width + col as usize)
+    }
+
+    // Cells counting towards `cell`'s neighbors, with their weights. On small
+    // toroidal grids an offset can wrap onto the same cell more than once.
+    pub fn neighbors(
+        &self,
+        width: usize,
+        height: usize,
+        cell: usize,
+    ) -> impl Iterator<Item = (usize, u32)> + '_ {
+        self.offsets.iter().filter_map(move |&(dx, dy, weight)| {
+            self.offset_cell(width, height, cell, dx, dy)
+                .map(|neighbor| (neighbor, weight))
+        })
+    }
+
+    // Cells that count `cell` as one of their neighbors, with the weight it adds
+    // to them. Differs from `neighbors` for kernels that aren't symmetric.
+    pub fn reverse_neighbors(
+        &self,
+        width: usize,
+        height: usize,
+        cell: usize,
+    ) -> impl Iterator<Item = (usize, u32)> + '_ {
+        self.offsets.iter().filter_map(move |&(dx, dy, weight)| {
+            self.offset_cell(width, height, cell, -dx, -dy)
+                .map(|neighbor| (neighbor, weight))
+        })
+    }
+
+    // Weighted number of rolls around `cell`.
+    pub fn count(&self, cells: &[u8], width: usize, height: usize, cell: usize) -> u32 {
+        self.neighbors(width, height, cell)
+            .map(|(neighbor, weight)| cells[neighbor] as u32 * weight)
+            .sum()
+    }
+
+    // How many rows above or below a roll its neighborhood reaches.
+    pub fn reach(&self) -> usize {
+        self.offsets
+            .iter()
+            .map(|&(_, dy, _)| dy.unsigned_abs() as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn is_accessible(&self, count: u32) -> bool {
+        count < self.threshold
+    }
+}
@@ -0,0 +1,303 @@
+use crate::bitgrid::{for_each_line, parse_row, RowClassifier};
+use crate::rules::Rules;
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{debug, info};
+
+// Classifies bit-packed rows as they stream past, holding only the rows a
+// neighborhood can reach instead of the whole grid.
+//
+// With a reach of r rows, row c is classified as soon as row c + r has been
+// pushed, using the last 2r + 1 rows. Toroidal grids also keep their first 2r
+// rows, since the top and bottom r rows can only be classified once the height
+// is known in `finish`. Either way memory depends on the width and the rules,
+// not the height.
+pub struct RowWindow<'a> {
+    classifier: RowClassifier<'a>,
+    toroidal: bool,
+    reach: usize,
+    width: usize,
+    first: Vec<Vec<u64>>,
+    recent: VecDeque<Vec<u64>>,
+    rows: usize,
+    accessible: Vec<u64>,
+}
+
+impl<'a> RowWindow<'a> {
+    pub fn new(rules: &'a Rules, width: usize) -> Self {
+        let words_per_row = width.div_ceil(64);
+        RowWindow {
+            classifier: RowClassifier::new(rules, words_per_row),
+            toroidal: rules.toroidal,
+            reach: rules.reach(),
+            width,
+            first: Vec::new(),
+            recent: VecDeque::new(),
+            rows: 0,
+            accessible: vec![0; words_per_row],
+        }
+    }
+
+    // Adds the next row of the grid. `on_row(row, cells, accessible)` is called
+    // for each row whose neighbors are now all known.
+    pub fn push(&mut self, cells: Vec<u64>, mut on_row: impl FnMut(usize, &[u64], &[u64])) {
+        if self.toroidal && self.first.len() < 2 * self.reach {
+            self.first.push(cells.clone());
+        }
+        self.recent.push_back(cells);
+        if self.recent.len() > 2 * self.reach + 1 {
+            self.recent.pop_front();
+        }
+        self.rows += 1;
+
+        let Some(row) = (self.rows - 1).checked_sub(self.reach) else {
+            return;
+        };
+        // rows near the top wrap around to rows that aren't read yet.
+        if self.toroidal && row < self.reach {
+            return;
+        }
+        let oldest = self.rows - self.recent.len();
+        let recent = &self.recent;
+        self.classifier.classify(
+            self.width,
+            &recent[row - oldest],
+            |dy| {
+                let neighbor_row = row as i64 + dy;
+                (neighbor_row >= oldest as i64)
+                    .then(|| recent[neighbor_row as usize - oldest].as_slice())
+            },
+            &mut self.accessible,
+        );
+        on_row(row, &recent[row - oldest], &self.accessible);
+    }
+
+    // Classifies the rows left over once the whole grid has been pushed: the
+    // last `reach` rows, and for toroidal grids the first `reach` rows too.
+    pub fn finish(mut self, mut on_row: impl FnMut(usize, &[u64], &[u64])) {
+        let height = self.rows;
+        let oldest = height - self.recent.len();
+        let leftover: Vec<usize> = if self.toroidal {
+            (0..height)
+                .filter(|&row| row < self.reach || row + self.reach >= height)
+                .collect()
+        } else {
+            (height.saturating_sub(self.reach)..height).collect()
+        };
+
+        let (first, recent, toroidal) = (&self.first, &self.recent, self.toroidal);
+        let stored_row = |row: usize| stored_row(first, recent, oldest, row);
+        for row in leftover {
+            self.classifier.classify(
+                self.width,
+                stored_row(row),
+                |dy| {
+                    let neighbor_row = row as i64 + dy;
+                    if toroidal {
+                        Some(stored_row(neighbor_row.rem_euclid(height as i64) as usize))
+                    } else if (0..height as i64).contains(&neighbor_row) {
+                        Some(stored_row(neighbor_row as usize))
+                    } else {
+                        None
+                    }
+                },
+                &mut self.accessible,
+            );
+            on_row(row, stored_row(row), &self.accessible);
+        }
+    }
+}
+
+// Row `row` of the grid, from the newest rows if it's still among them and from
+// the first rows otherwise.
+fn stored_row<'r>(
+    first: &'r [Vec<u64>],
+    recent: &'r VecDeque<Vec<u64>>,
+    oldest: usize,
+    row: usize,
+) -> &'r [u64] {
+    if row >= oldest {
+        &recent[row - oldest]
+    } else {
+        &first[row]
+    }
+}
+
+// Part 1 in a single read of the input, keeping only a window of rows around
+// the one being classified.
+pub fn count_accessible(input: impl BufRead, rules: &Rules) -> Result<u64> {
+    let mut window: Option<RowWindow> = None;
+    let mut accessible_rolls = 0;
+    let mut count_row = |_: usize, _: &[u64], accessible: &[u64]| {
+        accessible_rolls += accessible
+            .iter()
+            .map(|word| word.count_ones() as u64)
+            .sum::<u64>();
+    };
+    for_each_line(input, |row, line| {
+        let window = window.get_or_insert_with(|| RowWindow::new(rules, line.len()));
+        window.push(parse_row(line, window.width, row)?, &mut count_row);
+        Ok(())
+    })?;
+    if let Some(window) = window {
+        window.finish(&mut count_row);
+    }
+    Ok(accessible_rolls)
+}
+
+// A grid stored bit-packed in a file: `width.div_ceil(64)` little-endian words
+// per row, rows one after another. The file is removed when this is dropped.
+struct PackedGrid {
+    path: PathBuf,
+    width: usize,
+    height: usize,
+}
+
+impl PackedGrid {
+    fn row_bytes(&self) -> usize {
+        self.width.div_ceil(64) * 8
+    }
+
+    // Streams the rows of the grid in order.
+    fn for_each_row(&self, mut on_row: impl FnMut(Vec<u64>)) -> Result<()> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut bytes = vec![0u8; self.row_bytes()];
+        for _ in 0..self.height {
+            reader.read_exact(&mut bytes)?;
+            on_row(
+                bytes
+                    .chunks_exact(8)
+                    .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+                    .collect(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PackedGrid {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Writes rows of a packed grid, in any order. Until `finish` hands it over as a
+// `PackedGrid`, the file is removed if writing is abandoned.
+struct PackedWriter {
+    output: BufWriter<File>,
+    grid: PackedGrid,
+    row_bytes: usize,
+    next_row: usize,
+}
+
+impl PackedWriter {
+    fn create(path: &Path, width: usize) -> Result<Self> {
+        let output =
+            File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+        Ok(PackedWriter {
+            output: BufWriter::new(output),
+            grid: PackedGrid {
+                path: path.to_path_buf(),
+                width,
+                height: 0,
+            },
+            row_bytes: width.div_ceil(64) * 8,
+            next_row: 0,
+        })
+    }
+
+    fn write_row(&mut self, row: usize, cells: &[u64]) -> Result<()> {
+        // only the rows a toroidal window holds back arrive out of order.
+        if row != self.next_row {
+            self.output
+                .seek(SeekFrom::Start((row * self.row_bytes) as u64))?;
+        }
+        for word in cells {
+            self.output.write_all(&word.to_le_bytes())?;
+        }
+        self.next_row = row + 1;
+        Ok(())
+    }
+
+    fn finish(mut self, height: usize) -> Result<PackedGrid> {
+        self.output.flush()?;
+        self.grid.height = height;
+        Ok(self.grid)
+    }
+}
+
+// Part 2 for grids that don't fit in memory. The grid is kept bit-packed in
+// `work_dir` (an eighth of the size of the input) and every pass streams it
+// through a `RowWindow` into a second file, so memory only depends on the width.
+pub fn remove_rolls_on_disk(
+    input: impl BufRead,
+    rules: &Rules,
+    work_dir: &Path,
+) -> Result<Vec<u64>> {
+    // names unique to the process and the run, so runs can share a work dir.
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    let paths = ["a", "b"]
+        .map(|side| work_dir.join(format!("day4-grid-{}-{run}-{side}.bin", process::id())));
+
+    let mut width = None;
+    let mut height = 0;
+    let mut writer: Option<PackedWriter> = None;
+    for_each_line(input, |row, line| {
+        let width = *width.get_or_insert(line.len());
+        if writer.is_none() {
+            writer = Some(PackedWriter::create(&paths[0], width)?);
+        }
+        writer
+            .as_mut()
+            .unwrap()
+            .write_row(row, &parse_row(line, width, row)?)?;
+        height = row + 1;
+        Ok(())
+    })?;
+    let Some(writer) = writer else {
+        return Ok(Vec::new());
+    };
+    let mut grid = writer.finish(height)?;
+    let width = grid.width;
+    info!("Input grid has width: {width}, height: {height}");
+
+    let mut removed_per_pass = Vec::new();
+    for pass in 1.. {
+        let mut next = PackedWriter::create(&paths[pass % 2], width)?;
+        let mut removed = 0;
+        let mut result = Ok(());
+        let mut remove_row = |row: usize, cells: &[u64], accessible: &[u64]| {
+            removed += accessible
+                .iter()
+                .map(|word| word.count_ones() as u64)
+                .sum::<u64>();
+            let remaining: Vec<u64> = cells
+                .iter()
+                .zip(accessible)
+                .map(|(cell, accessible)| cell & !accessible)
+                .collect();
+            if result.is_ok() {
+                result = next.write_row(row, &remaining);
+            }
+        };
+        let mut window = RowWindow::new(rules, width);
+        grid.for_each_row(|cells| window.push(cells, &mut remove_row))?;
+        window.finish(&mut remove_row);
+        result?;
+        let next_grid = next.finish(height)?;
+
+        if removed == 0 {
+            break;
+        }
+        debug!("Pass {pass}: removed {removed} rolls");
+        removed_per_pass.push(removed);
+        grid = next_grid;
+    }
+    Ok(removed_per_pass)
+}
@@ -0,0 +1,52 @@
+use crate::{count_ids, read_merged_ranges};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Args)]
+pub struct GapsArgs {
+    /// Database to audit, in the day 5 format or a compiled index
+    input_path: PathBuf,
+}
+
+// Spoiled ID intervals between merged ranges, from the smallest fresh ID to the
+// largest. Touching ranges leave no gap between them.
+pub fn find_gaps(ranges: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    ranges
+        .windows(2)
+        .filter(|pair| pair[0].1 + 1 < pair[1].0)
+        .map(|pair| (pair[0].1 + 1, pair[1].0 - 1))
+        .collect()
+}
+
+pub fn gaps(args: &GapsArgs) -> Result<()> {
+    let input = File::open(&args.input_path)
+        .with_context(|| format!("Unable to open {}", args.input_path.display()))?;
+    let ranges = read_merged_ranges(&mut BufReader::new(input), None)?;
+    let (Some(first), Some(last)) = (ranges.first(), ranges.last()) else {
+        eprintln!("No fresh ranges");
+        return Ok(());
+    };
+
+    let gaps = find_gaps(&ranges);
+    let largest = gaps.iter().map(|(start, end)| end - start).max();
+    let mut output = BufWriter::new(io::stdout().lock());
+    for &(start, end) in &gaps {
+        let length = end - start;
+        write!(output, "{start}-{end}: {} IDs", length as u128 + 1)?;
+        if Some(length) == largest {
+            write!(output, " (largest)")?;
+        }
+        writeln!(output)?;
+    }
+    output.flush()?;
+    eprintln!(
+        "{} spoiled IDs in {} gaps between {} and {}",
+        count_ids(&gaps),
+        gaps.len(),
+        first.0,
+        last.1
+    );
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use crate::validity::Date;
+use crate::{coalesce_ranges, read_merged_ranges};
+use anyhow::{bail, ensure, Context, Result};
+use shared::crc32;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+// Binary index of a database's merged fresh ranges, much quicker to load than
+// the text. Layout, all integers little-endian or LEB128 varints:
+//
+//   magic     4 bytes, `MAGIC`
+//   version   1 byte, `VERSION`
+//   count     varint, number of ranges
+//   ranges    per range a varint gap from the previous range's end (from 0 for
+//             the first range) to its start, then a varint of end - start
+//   checksum  4 bytes, CRC-32 of everything before it
+//
+// The ranges are merged, so they are sorted and the gaps never go negative.
+// The magic starts with a NUL byte, which no text database can start with.
+pub const MAGIC: &[u8; 4] = b"\0D5X";
+const VERSION: u8 = 1;
+
+#[derive(Debug, clap::Args)]
+pub struct CompileArgs {
+    /// Text database to read the fresh ranges from
+    input_path: PathBuf,
+    /// Where to write the index
+    output_path: PathBuf,
+    /// Also join ranges that touch without overlapping, like 3-5 and 6-8
+    #[arg(long)]
+    coalesce: bool,
+    /// Only compile the ranges fresh on this date (YYYY-MM-DD); the index has
+    /// no validity windows of its own
+    #[arg(long)]
+    as_of: Option<Date>,
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let Some(&byte) = bytes.get(*pos) else {
+            bail!("Index ends in the middle of a number");
+        };
+        *pos += 1;
+        ensure!(
+            shift < 63 || byte <= 1,
+            "Index holds a number too large for 64 bits"
+        );
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Index holds a number too large for 64 bits")
+}
+
+// Encodes merged (sorted, disjoint) ranges.
+pub fn encode(ranges: &[(u64, u64)]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    write_varint(&mut bytes, ranges.len() as u64);
+    let mut previous_end = 0;
+    for &(start, end) in ranges {
+        write_varint(&mut bytes, start - previous_end);
+        write_varint(&mut bytes, end - start);
+        previous_end = end;
+    }
+    let checksum = crc32(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<(u64, u64)>> {
+    ensure!(bytes.starts_with(MAGIC), "Not a day 5 index");
+    ensure!(bytes.len() >= MAGIC.len() + 1 + 4, "Index is truncated");
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    ensure!(
+        crc32(body) == u32::from_le_bytes(checksum.try_into()?),
+        "Index checksum does not match, the file is damaged"
+    );
+    let version = body[MAGIC.len()];
+    ensure!(
+        version == VERSION,
+        "Index version {version} is not supported, expected {VERSION}"
+    );
+
+    let mut pos = MAGIC.len() + 1;
+    let count = read_varint(body, &mut pos)?;
+    let mut ranges = Vec::with_capacity(count.min(body.len() as u64) as usize);
+    let mut previous_end = 0u64;
+    for idx in 0..count {
+        let start = previous_end
+            .checked_add(read_varint(body, &mut pos)?)
+            .context("Index range starts past the largest ID")?;
+        let end = start
+            .checked_add(read_varint(body, &mut pos)?)
+            .context("Index range ends past the largest ID")?;
+        // the very first range may start at 0, every later one after a gap.
+        ensure!(
+            idx == 0 || start > previous_end,
+            "Index ranges are not sorted and disjoint"
+        );
+        ranges.push((start, end));
+        previous_end = end;
+    }
+    ensure!(
+        pos == body.len(),
+        "Index has trailing data after its ranges"
+    );
+    Ok(ranges)
+}
+
+pub fn compile(args: &CompileArgs) -> Result<()> {
+    let input = File::open(&args.input_path)
+        .with_context(|| format!("Unable to open {}", args.input_path.display()))?;
+    let mut ranges = read_merged_ranges(&mut BufReader::new(input), args.as_of)?;
+    if args.coalesce {
+        ranges = coalesce_ranges(ranges);
+    }
+    let bytes = encode(&ranges);
+
+    let mut output = BufWriter::new(
+        File::create(&args.output_path)
+            .with_context(|| format!("Unable to create {}", args.output_path.display()))?,
+    );
+    output.write_all(&bytes)?;
+    output.flush()?;
+    info!(
+        "Wrote {} merged ranges in {} bytes to {}",
+        ranges.len(),
+        bytes.len(),
+        args.output_path.display()
+    );
+    Ok(())
+}
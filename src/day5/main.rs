@@ -40,116 +40,304 @@ Process the database file from the new inventory management system. How many of
 
 */
 
-use anyhow::Result;
+mod gaps;
+mod index;
+mod provenance;
+mod serve;
+mod setop;
+mod validity;
+
+use anyhow::{bail, ensure, Context, Result};
 use regex::Regex;
-use shared::shared_main;
-use std::cmp::Ordering;
-use std::collections::HashSet;
+use shared::shared_main_with_commands;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::ops::RangeInclusive;
 use tracing::{debug, info};
+use validity::{Date, Validity};
 
 fn main() -> Result<()> {
-    shared_main(solution1, solution2)
+    shared_main_with_commands(solution1, solution2, run_command)
 }
 
-fn solution1(input: &File) -> Result<u64> {
-    let range_regex = Regex::new(r"^(\d+)-(\d+)")?;
-    let mut solution = 0;
+#[derive(Debug, Default, clap::Args)]
+struct Options {
+    /// Also join ranges that touch without overlapping, like 3-5 and 6-8
+    #[arg(long)]
+    coalesce: bool,
+    /// Only use the ranges fresh on this date (YYYY-MM-DD); without it every
+    /// range is used, whatever its validity window
+    #[arg(long)]
+    as_of: Option<Date>,
+}
 
-    let mut ranges = Vec::new();
-
-    // process the input file, extracting ranges.
-    let mut input_reader = BufReader::new(input);
-    loop {
-        // Read until a delimiter
-        let mut range_bytes = Vec::new();
-        let delim = b'\n';
-        let bytes = input_reader.read_until(delim, &mut range_bytes)?;
-        if bytes == 0 || bytes == 1 {
-            break;
-        }
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Load the fresh ranges of a database once, then answer ingredient IDs
+    /// with `fresh` or `spoiled`, one per line
+    Serve(serve::ServeArgs),
+    /// List every original range covering each ingredient ID, with the line
+    /// it is on and how many ranges overlap there
+    Trace(provenance::TraceArgs),
+    /// Combine the fresh ranges of two or more databases, printing the merged
+    /// ranges of the result
+    Setop(setop::SetopArgs),
+    /// Compile the fresh ranges of a database into a binary index, which part 2,
+    /// `serve`, `setop` and `gaps` accept in place of the text
+    Compile(index::CompileArgs),
+    /// List the spoiled ID intervals between the smallest and largest fresh ID,
+    /// with their lengths and the largest one marked
+    Gaps(gaps::GapsArgs),
+    /// Print the ranges that became fresh (+) or stopped being fresh (-)
+    /// between two dates, going by the validity windows of the ranges
+    Diff(validity::DiffArgs),
+}
 
-        if range_bytes.last() == Some(&delim) {
-            range_bytes.pop();
-        }
+fn run_command(command: &Command) -> Result<()> {
+    match command {
+        Command::Serve(args) => serve::serve(args),
+        Command::Trace(args) => provenance::trace(args),
+        Command::Setop(args) => setop::setop(args),
+        Command::Compile(args) => index::compile(args),
+        Command::Gaps(args) => gaps::gaps(args),
+        Command::Diff(args) => validity::diff(args),
+    }
+}
 
-        let range_str = String::from_utf8(range_bytes)?;
-        let Some((_, [range_start, range_end])) =
-            range_regex.captures(&range_str).map(|c| c.extract())
-        else {
-            panic!("Unable to parse input range");
-        };
+// A fresh ID range as written in the database, optionally followed by the
+// dates it applies on, as in `3-5 @2025-12-01..2025-12-24`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SourceRange {
+    start: u64,
+    end: u64,
+    // 1-based line of the database the range is on
+    line: usize,
+    window: Validity,
+}
 
-        // construct a new range using the extracted values
-        ranges.push((range_start.parse::<u64>()?, range_end.parse::<u64>()?));
+impl SourceRange {
+    fn bounds(&self) -> (u64, u64) {
+        (self.start, self.end)
     }
+}
 
-    // Sort all the ranges by minimum, then maximum.
-    ranges.sort_by(|a, b| {
-        if a.0 != b.0 {
-            a.0.cmp(&b.0)
-        } else {
-            a.1.cmp(&b.1)
+// Reads a database a line at a time, numbering the lines for error messages.
+// The fresh ID ranges come first, then a blank line, then the available IDs;
+// either section may be empty, and lines may end in `\n` or `\r\n`.
+struct DatabaseReader<R> {
+    input: R,
+    // number of lines read so far
+    line: usize,
+    text: String,
+    // first blank line seen among the available IDs
+    blank_line: Option<usize>,
+}
+
+impl<R: BufRead> DatabaseReader<R> {
+    fn new(input: R) -> Self {
+        Self {
+            input,
+            line: 0,
+            text: String::new(),
+            blank_line: None,
         }
-    });
+    }
 
-    debug!("Ranges: {:?}", ranges);
+    // Reads the next line into `text` without its line ending, returning false
+    // at the end of the input.
+    fn next_line(&mut self) -> Result<bool> {
+        self.text.clear();
+        let bytes = self
+            .input
+            .read_line(&mut self.text)
+            .with_context(|| format!("Unable to read line {}", self.line + 1))?;
+        if bytes == 0 {
+            return Ok(false);
+        }
+        self.line += 1;
+        if self.text.ends_with('\n') {
+            self.text.pop();
+            if self.text.ends_with('\r') {
+                self.text.pop();
+            }
+        }
+        Ok(true)
+    }
+
+    // Reads the fresh ID ranges, up to and including the blank line that ends
+    // them.
+    fn ranges(&mut self) -> Result<Vec<SourceRange>> {
+        ensure!(
+            self.line > 0 || !self.input.fill_buf()?.starts_with(index::MAGIC),
+            "This is a compiled index, which only holds merged ranges; use the text database"
+        );
+        let range_regex = Regex::new(r"^(\d+)-(\d+)(?:\s+@(\S*))?$")?;
+        let mut ranges = Vec::new();
+        while self.next_line()? {
+            let text = self.text.trim();
+            if text.is_empty() {
+                break;
+            }
+            let Some(captures) = range_regex.captures(text) else {
+                if text.parse::<u64>().is_ok() {
+                    bail!(
+                        "Line {}: expected a fresh ID range like 3-5, got {text:?}; \
+                         is the blank line before the available IDs missing?",
+                        self.line
+                    );
+                }
+                bail!(
+                    "Line {}: expected a fresh ID range like 3-5, got {text:?}",
+                    self.line
+                );
+            };
+            let parse_bound = |bound: &str| {
+                bound
+                    .parse::<u64>()
+                    .with_context(|| format!("Line {}: invalid range bound {bound}", self.line))
+            };
+            let (start, end) = (parse_bound(&captures[1])?, parse_bound(&captures[2])?);
+            ensure!(
+                start <= end,
+                "Line {}: range {start}-{end} ends before it starts",
+                self.line
+            );
+            let window = match captures.get(3) {
+                Some(window) => window
+                    .as_str()
+                    .parse()
+                    .with_context(|| format!("Line {}: invalid validity window", self.line))?,
+                None => Validity::default(),
+            };
+            ranges.push(SourceRange {
+                start,
+                end,
+                line: self.line,
+                window,
+            });
+        }
+        Ok(ranges)
+    }
+
+    // Reads the next available ingredient ID, once the ranges have been read.
+    // Blank lines are only allowed at the end of the input.
+    fn next_id(&mut self) -> Result<Option<u64>> {
+        while self.next_line()? {
+            let text = self.text.trim();
+            if text.is_empty() {
+                self.blank_line.get_or_insert(self.line);
+                continue;
+            }
+            if let Some(blank_line) = self.blank_line {
+                bail!(
+                    "Line {blank_line}: blank line between available IDs; \
+                     a database has only two sections"
+                );
+            }
+            let id = text
+                .parse::<u64>()
+                .with_context(|| format!("Line {}: invalid ingredient ID {text:?}", self.line))?;
+            return Ok(Some(id));
+        }
+        Ok(None)
+    }
 
-    // then, iterate and compute the union of all ranges.
-    let mut current_idx = 0;
-    let mut ranges_length = ranges.len().clone();
-    while current_idx < ranges.len() - 1 {
-        // if this range overlaps with the next range, merge them.
-        let (start1, end1) = ranges[current_idx];
-        let (start2, end2) = ranges[current_idx + 1];
-        // overlap occurs if the end or start of one range lies between the other range.
-        if !(end1 < start2 || start1 > end2) {
-            debug!("merging ranges: {start1}-{end1} and {start2}-{end2}");
-            ranges[current_idx] = (start1.min(start2), end1.max(end2));
-            ranges.remove(current_idx + 1);
-            continue;
+    // Reads the rest of the available ingredient IDs.
+    fn ids(&mut self) -> Result<Vec<u64>> {
+        let mut ids = Vec::new();
+        while let Some(id) = self.next_id()? {
+            ids.push(id);
         }
+        Ok(ids)
+    }
+}
 
-        current_idx += 1;
+// Merges the ranges fresh on `as_of`, or all of them without a date.
+fn ranges_as_of(ranges: &[SourceRange], as_of: Option<Date>) -> Vec<(u64, u64)> {
+    merge_ranges(
+        ranges
+            .iter()
+            .filter(|range| as_of.is_none_or(|date| range.window.contains(date)))
+            .map(SourceRange::bounds)
+            .collect(),
+    )
+}
+
+// Reads the fresh ID ranges and merges those fresh on `as_of`. A compiled index
+// already holds merged ranges and is decoded as is.
+fn read_merged_ranges(
+    input_reader: &mut impl BufRead,
+    as_of: Option<Date>,
+) -> Result<Vec<(u64, u64)>> {
+    if input_reader.fill_buf()?.starts_with(index::MAGIC) {
+        ensure!(
+            as_of.is_none(),
+            "A compiled index has no validity windows; compile it with --as-of instead"
+        );
+        let mut bytes = Vec::new();
+        input_reader.read_to_end(&mut bytes)?;
+        return index::decode(&bytes);
     }
+    let ranges = DatabaseReader::new(input_reader).ranges()?;
+    Ok(ranges_as_of(&ranges, as_of))
+}
+
+// Sorts the ranges and merges the overlapping ones, leaving disjoint ranges in
+// increasing order.
+fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    // Sort all the ranges by minimum, then maximum.
+    ranges.sort_unstable();
 
-    debug!("Ranges after merging: {:?}", ranges);
+    debug!("Ranges: {:?}", ranges);
 
-    let get_containing_range = |ranges: &Vec<(u64, u64)>, value: u64| -> Option<usize> {
-        // use binary search to find if a range contains this element.
-        match ranges.binary_search_by(|p| {
-            if value > p.0 && value > p.1 {
-                return Ordering::Less;
-            } else if value < p.0 && value < p.1 {
-                return Ordering::Greater;
+    // then, extend the last merged range with every range that overlaps it.
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => {
+                debug!("merging ranges: {}-{} and {start}-{end}", last.0, last.1);
+                last.1 = last.1.max(end);
             }
-            Ordering::Equal
-        }) {
-            Ok(val) => Some(val),
-            Err(_) => None,
+            _ => merged.push((start, end)),
         }
-    };
+    }
 
-    // skip the newline in the input
-    input_reader.read_line(&mut String::new());
+    debug!("Ranges after merging: {:?}", merged);
+    merged
+}
 
-    loop {
-        let mut value_bytes = Vec::new();
-        let delim = b'\n';
-        let bytes = input_reader.read_until(delim, &mut value_bytes)?;
-        if bytes == 0 {
-            break;
+// Joins merged ranges that touch, like 3-5 and 6-8, which merging keeps apart
+// since they don't overlap.
+fn coalesce_ranges(ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    let mut coalesced: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match coalesced.last_mut() {
+            Some(last) if last.1.checked_add(1) == Some(start) => last.1 = end,
+            _ => coalesced.push((start, end)),
         }
+    }
+    debug!("Ranges after coalescing: {:?}", coalesced);
+    coalesced
+}
 
-        if value_bytes.last() == Some(&delim) {
-            value_bytes.pop();
-        }
+// Index of the merged range containing `value`, if any.
+fn get_containing_range(ranges: &[(u64, u64)], value: u64) -> Option<usize> {
+    // the ranges are disjoint and sorted, so their ends are sorted too: only the
+    // first range ending at or after `value` can contain it.
+    let index = ranges.partition_point(|&(_, end)| end < value);
+    (index < ranges.len() && ranges[index].0 <= value).then_some(index)
+}
 
-        let value = String::from_utf8(value_bytes)?;
-        let value = value.parse::<u64>()?;
+// Number of available IDs in the database that fall in a fresh range.
+fn count_fresh(input_reader: impl BufRead, options: &Options) -> Result<u64> {
+    let mut solution = 0;
+
+    let mut database = DatabaseReader::new(input_reader);
+    let mut ranges = ranges_as_of(&database.ranges()?, options.as_of);
+    if options.coalesce {
+        ranges = coalesce_ranges(ranges);
+    }
 
+    while let Some(value) = database.next_id()? {
         if let Some(index) = get_containing_range(&ranges, value) {
             solution += 1;
             info!(
@@ -162,6 +350,10 @@ fn solution1(input: &File) -> Result<u64> {
     Ok(solution)
 }
 
+fn solution1(input: &File, options: &Options) -> Result<u64> {
+    count_fresh(BufReader::new(input), options)
+}
+
 /*
 --- Part Two ---
 
@@ -182,88 +374,469 @@ Process the database file again. How many ingredient IDs are considered to be fr
 
 */
 
-fn solution2(input: &File) -> Result<u64> {
-    let range_regex = Regex::new(r"^(\d+)-(\d+)")?;
+// Number of IDs in disjoint ranges. Wide enough for a range covering every u64.
+fn count_ids(ranges: &[(u64, u64)]) -> u128 {
     let mut solution = 0;
+    for range in ranges {
+        // compute the width of this range, and add to our running total of fresh
+        // ingredient ids.
+        solution += (range.1 - range.0) as u128 + 1;
+    }
+    solution
+}
+
+fn solution2(input: &File, options: &Options) -> Result<u64> {
+    let mut ranges = read_merged_ranges(&mut BufReader::new(input), options.as_of)?;
+    if options.coalesce {
+        ranges = coalesce_ranges(ranges);
+    }
+    let solution = count_ids(&ranges);
+    u64::try_from(solution).with_context(|| format!("{solution} fresh IDs do not fit in a u64"))
+}
 
-    let mut ranges = Vec::new();
-
-    // process the input file, extracting ranges.
-    let mut input_reader = BufReader::new(input);
-    loop {
-        // Read until a delimiter
-        let mut range_bytes = Vec::new();
-        let delim = b'\n';
-        let bytes = input_reader.read_until(delim, &mut range_bytes)?;
-        if bytes == 0 || bytes == 1 {
-            break;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gaps::find_gaps;
+    use provenance::RangeIndex;
+    use setop::{combine, SetOperation};
+    use shared::test_util::XorShift;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::io::{self, Cursor, Read, Write};
+    use std::rc::Rc;
+
+    // Deterministic pseudo random numbers below `bound`.
+    fn random_values(count: usize, bound: u64, seed: u64) -> Vec<u64> {
+        let mut random = XorShift::new(seed);
+        (0..count).map(|_| random.below(bound)).collect()
+    }
+
+    #[test]
+    fn lookup_at_range_boundaries() {
+        // adjacent ranges stay separate, single IDs and the top of u64 included
+        let ranges = merge_ranges(vec![(6, 8), (3, 5), (10, 10), (20, u64::MAX)]);
+        assert_eq!(ranges, vec![(3, 5), (6, 8), (10, 10), (20, u64::MAX)]);
+        for value in (0..30).chain([u64::MAX - 1, u64::MAX]) {
+            let expected = ranges
+                .iter()
+                .position(|&(start, end)| start <= value && value <= end);
+            assert_eq!(get_containing_range(&ranges, value), expected, "ID {value}");
         }
+        assert_eq!(merge_ranges(vec![]), vec![]);
+        assert_eq!(get_containing_range(&[], 0), None);
+        assert_eq!(get_containing_range(&[(0, 0)], 0), Some(0));
+    }
 
-        if range_bytes.last() == Some(&delim) {
-            range_bytes.pop();
+    #[test]
+    fn merge_matches_membership() {
+        for seed in 1..20 {
+            let bounds = random_values(2 * 200, 1000, seed);
+            let ranges: Vec<(u64, u64)> = bounds
+                .chunks(2)
+                .map(|pair| (pair[0].min(pair[1]), pair[0].max(pair[1])))
+                .collect();
+            let merged = merge_ranges(ranges.clone());
+            // sorted and disjoint, though they may touch.
+            assert!(merged.windows(2).all(|pair| pair[0].1 < pair[1].0));
+            for value in 0..1000 {
+                assert_eq!(
+                    get_containing_range(&merged, value).is_some(),
+                    ranges
+                        .iter()
+                        .any(|&(start, end)| start <= value && value <= end),
+                    "ID {value} with seed {seed}"
+                );
+            }
         }
+        // ranges nested in, or duplicating, an earlier one
+        assert_eq!(
+            merge_ranges(vec![(1, 100), (5, 6), (1, 100), (50, 120), (121, 130)]),
+            vec![(1, 120), (121, 130)]
+        );
+
+        // a million nested ranges merge in linear time after sorting.
+        let nested: Vec<(u64, u64)> = (0..1_000_000).map(|i| (i, 2_000_000 - i)).collect();
+        assert_eq!(merge_ranges(nested), vec![(0, 2_000_000)]);
+    }
 
-        let range_str = String::from_utf8(range_bytes)?;
-        let Some((_, [range_start, range_end])) =
-            range_regex.captures(&range_str).map(|c| c.extract())
-        else {
-            panic!("Unable to parse input range");
+    #[test]
+    fn coalescing_and_gaps() {
+        let ranges = merge_ranges(vec![(3, 5), (6, 8), (10, 14), (20, 20), (12, 18)]);
+        assert_eq!(ranges, vec![(3, 5), (6, 8), (10, 18), (20, 20)]);
+        assert_eq!(find_gaps(&ranges), vec![(9, 9), (19, 19)]);
+        let coalesced = coalesce_ranges(ranges);
+        assert_eq!(coalesced, vec![(3, 8), (10, 18), (20, 20)]);
+        assert_eq!(find_gaps(&coalesced), vec![(9, 9), (19, 19)]);
+
+        let limits = vec![(0, 0), (1, 1), (u64::MAX, u64::MAX)];
+        assert_eq!(find_gaps(&limits), vec![(2, u64::MAX - 1)]);
+        assert_eq!(coalesce_ranges(limits), vec![(0, 1), (u64::MAX, u64::MAX)]);
+        assert_eq!(find_gaps(&[]), vec![]);
+        assert_eq!(coalesce_ranges(vec![]), vec![]);
+
+        // the fresh ranges and the gaps between them tile the span exactly
+        for seed in 1..=20u64 {
+            let values = random_values(2 * seed as usize, 200, seed);
+            let ranges = coalesce_ranges(merge_ranges(
+                values
+                    .chunks(2)
+                    .map(|pair| (pair[0].min(pair[1]), pair[0].max(pair[1])))
+                    .collect(),
+            ));
+            assert!(ranges.windows(2).all(|pair| pair[0].1 + 1 < pair[1].0));
+            let gaps = find_gaps(&ranges);
+            let (first, last) = (ranges[0].0, ranges[ranges.len() - 1].1);
+            assert_eq!(
+                count_ids(&ranges) + count_ids(&gaps),
+                (last - first) as u128 + 1
+            );
+            assert!(gaps
+                .iter()
+                .all(
+                    |&(start, end)| get_containing_range(&ranges, start).is_none()
+                        && get_containing_range(&ranges, end).is_none()
+                ));
+        }
+    }
+
+    #[test]
+    fn sections_parse_strictly() {
+        let count = |text: &str| count_fresh(Cursor::new(text), &Options::default());
+        assert_eq!(count("3-5\n6-8\n\n2\n3\n5\n6\n8\n9\n").unwrap(), 4);
+        assert_eq!(
+            count("3-5\r\n6-8\r\n\r\n2\r\n3\r\n5\r\n6\r\n8\r\n9").unwrap(),
+            4
+        );
+        assert_eq!(count("3-5\n\n5\n\n\n").unwrap(), 1);
+        // either section can be empty
+        assert_eq!(count("").unwrap(), 0);
+        assert_eq!(count("\n5\n").unwrap(), 0);
+        assert_eq!(count("3-5\n").unwrap(), 0);
+
+        for (text, message) in [
+            ("3-5\nabc\n\n1\n", "Line 2: expected a fresh ID range"),
+            (
+                "3-5\n4\n",
+                "is the blank line before the available IDs missing?",
+            ),
+            ("3-5\n5-3\n", "Line 2: range 5-3 ends before it starts"),
+            ("1-99999999999999999999\n", "Line 1: invalid range bound"),
+            ("3-5\n\n4\nfour\n", "Line 4: invalid ingredient ID"),
+            (
+                "3-5\n\n4\n\n5\n",
+                "Line 4: blank line between available IDs",
+            ),
+        ] {
+            let error = count(text).unwrap_err();
+            assert!(
+                format!("{error:#}").contains(message),
+                "{text:?}: {error:#}"
+            );
+        }
+    }
+
+    #[test]
+    fn validity_windows() {
+        let date = |text: &str| text.parse::<Date>().unwrap();
+        assert!(date("2024-02-29") < date("2024-03-01"));
+        for text in [
+            "2025-02-29",
+            "2025-13-01",
+            "2025-12-1",
+            "2025-12-32",
+            "12/01/2025",
+        ] {
+            assert!(text.parse::<Date>().is_err(), "{text}");
+        }
+        let window = "2025-12-01..2025-12-24".parse::<Validity>().unwrap();
+        assert_eq!(window.to_string(), "2025-12-01..2025-12-24");
+        let on = |window: &str, day: &str| window.parse::<Validity>().unwrap().contains(date(day));
+        assert!(on("2025-12-01..2025-12-24", "2025-12-01"));
+        assert!(on("2025-12-01..2025-12-24", "2025-12-24"));
+        assert!(!on("2025-12-01..2025-12-24", "2025-11-30"));
+        assert!(!on("2025-12-01..2025-12-24", "2025-12-25"));
+        assert!(on("..2025-12-24", "1999-01-01") && !on("..2025-12-24", "2026-01-01"));
+        assert!(on("2025-12-01..", "2099-12-31") && !on("2025-12-01..", "2025-01-01"));
+        assert!(on("..", "2025-06-15"));
+        assert!("2025-12-24..2025-12-01".parse::<Validity>().is_err());
+        assert!("2025-12-01".parse::<Validity>().is_err());
+
+        let text = "3-5 @2025-12-01..2025-12-24\n10-14\n12-18 @2025-12-10..\n\n4\n13\n17\n";
+        let options = |as_of: Option<&str>| Options {
+            as_of: as_of.map(date),
+            ..Options::default()
         };
+        let count = |as_of| count_fresh(Cursor::new(text), &options(as_of)).unwrap();
+        let merged = |as_of: Option<&str>| {
+            read_merged_ranges(&mut Cursor::new(text), as_of.map(date)).unwrap()
+        };
+        assert_eq!(count(None), 3);
+        assert_eq!(merged(None), vec![(3, 5), (10, 18)]);
+        assert_eq!(count(Some("2025-11-30")), 1);
+        assert_eq!(merged(Some("2025-11-30")), vec![(10, 14)]);
+        assert_eq!(count(Some("2025-12-10")), 3);
+        assert_eq!(count(Some("2025-12-25")), 2);
+        assert_eq!(merged(Some("2025-12-25")), vec![(10, 18)]);
+
+        // plain databases read the same on any date
+        let plain = "3-5\n10-14\n16-20\n12-18\n\n1\n5\n8\n11\n17\n32\n";
+        for as_of in [None, Some("2025-12-01")] {
+            assert_eq!(count_fresh(Cursor::new(plain), &options(as_of)).unwrap(), 3);
+        }
+        let error = count_fresh(Cursor::new("3-5 @soon\n"), &options(None)).unwrap_err();
+        assert!(format!("{error:#}").contains("Line 1: invalid validity window"));
+        let bytes = index::encode(&[(3, 5)]);
+        assert!(read_merged_ranges(&mut Cursor::new(&bytes), Some(date("2025-12-01"))).is_err());
+    }
 
-        // construct a new range using the extracted values
-        ranges.push((range_start.parse::<u64>()?, range_end.parse::<u64>()?));
+    #[test]
+    fn range_index_matches_scan() {
+        for (seed, count) in [0, 1, 2, 7, 100, 1000].into_iter().enumerate() {
+            let values = random_values(2 * count, 500, seed as u64 + 1);
+            let ranges: Vec<SourceRange> = values
+                .chunks(2)
+                .enumerate()
+                .map(|(idx, pair)| SourceRange {
+                    start: pair[0].min(pair[1]),
+                    end: pair[0].max(pair[1]),
+                    line: idx + 1,
+                    window: Validity::default(),
+                })
+                .collect();
+            let index = RangeIndex::new(ranges.clone());
+            for value in 0..510 {
+                let expected: Vec<SourceRange> = ranges
+                    .iter()
+                    .filter(|range| range.start <= value && value <= range.end)
+                    .copied()
+                    .collect();
+                assert_eq!(
+                    index.covering(value),
+                    expected,
+                    "{count} ranges, ID {value}"
+                );
+            }
+        }
     }
 
-    // Sort all the ranges by minimum, then maximum.
-    ranges.sort_by(|a, b| {
-        if a.0 != b.0 {
-            a.0.cmp(&b.0)
-        } else {
-            a.1.cmp(&b.1)
+    #[test]
+    fn set_operations_match_membership() {
+        let operations = [
+            SetOperation::Union,
+            SetOperation::Intersection,
+            SetOperation::Difference,
+            SetOperation::SymmetricDifference,
+        ];
+        for seed in 1..=30u64 {
+            let num_sets = 2 + seed as usize % 3;
+            let sets: Vec<Vec<(u64, u64)>> = (0..num_sets as u64)
+                .map(|set| {
+                    let values = random_values(2 * (seed as usize % 6), 60, seed * 10 + set);
+                    let ranges = values
+                        .chunks(2)
+                        .map(|pair| (pair[0].min(pair[1]), pair[0].max(pair[1])))
+                        .collect::<Vec<_>>();
+                    merge_ranges(ranges)
+                })
+                .collect();
+
+            for operation in operations {
+                let result = combine(operation, &sets);
+                let expected: Vec<u64> = (0..70)
+                    .filter(|&id| {
+                        let fresh_in: Vec<bool> = sets
+                            .iter()
+                            .map(|ranges| {
+                                ranges.iter().any(|&(start, end)| start <= id && id <= end)
+                            })
+                            .collect();
+                        match operation {
+                            SetOperation::Union => fresh_in.iter().any(|&fresh| fresh),
+                            SetOperation::Intersection => fresh_in.iter().all(|&fresh| fresh),
+                            SetOperation::Difference => {
+                                fresh_in[0] && fresh_in[1..].iter().all(|&fresh| !fresh)
+                            }
+                            SetOperation::SymmetricDifference => {
+                                fresh_in.iter().filter(|&&fresh| fresh).count() % 2 == 1
+                            }
+                        }
+                    })
+                    .collect();
+                let ids: Vec<u64> = result
+                    .iter()
+                    .flat_map(|&(start, end)| start..=end)
+                    .collect();
+                assert_eq!(ids, expected, "{operation:?} of {sets:?}");
+                // canonical: sorted, and neither overlapping nor touching
+                assert!(result.windows(2).all(|pair| pair[0].1 + 1 < pair[1].0));
+                assert_eq!(count_ids(&result), expected.len() as u128);
+            }
         }
-    });
+    }
 
-    debug!("Ranges: {:?}", ranges);
+    #[test]
+    fn set_operations_at_u64_limits() {
+        let sets = vec![vec![(0, u64::MAX)], vec![(5, 9), (u64::MAX, u64::MAX)]];
+        assert_eq!(
+            combine(SetOperation::Difference, &sets),
+            vec![(0, 4), (10, u64::MAX - 1)]
+        );
+        assert_eq!(combine(SetOperation::Union, &sets), vec![(0, u64::MAX)]);
+        assert_eq!(count_ids(&[(0, u64::MAX)]), 1 << 64);
+    }
 
-    // then, iterate and compute the union of all ranges.
-    let mut current_idx = 0;
-    let mut ranges_length = ranges.len().clone();
-    while current_idx < ranges.len() - 1 {
-        // if this range overlaps with the next range, merge them.
-        let (start1, end1) = ranges[current_idx];
-        let (start2, end2) = ranges[current_idx + 1];
-        // overlap occurs if the end or start of one range lies between the other range.
-        if !(end1 < start2 || start1 > end2) {
-            debug!("merging ranges: {start1}-{end1} and {start2}-{end2}");
-            ranges[current_idx] = (start1.min(start2), end1.max(end2));
-            ranges.remove(current_idx + 1);
-            continue;
+    #[test]
+    fn index_round_trips_text() {
+        let text = "3-5\n10-14\n16-20\n12-18\n\n1\n5\n";
+        let ranges = read_merged_ranges(&mut Cursor::new(text), None).unwrap();
+        assert_eq!(ranges, vec![(3, 5), (10, 20)]);
+        let bytes = index::encode(&ranges);
+        assert_eq!(
+            read_merged_ranges(&mut Cursor::new(&bytes), None).unwrap(),
+            ranges
+        );
+        assert!(DatabaseReader::new(Cursor::new(&bytes)).ranges().is_err());
+
+        for seed in 1..=20u64 {
+            let values = random_values(2 * seed as usize, 1 << (3 * seed), seed);
+            let mut ranges: Vec<(u64, u64)> = values
+                .chunks(2)
+                .map(|pair| (pair[0].min(pair[1]), pair[0].max(pair[1])))
+                .collect();
+            ranges.push((u64::MAX - 1, u64::MAX));
+            let ranges = merge_ranges(ranges);
+            assert_eq!(index::decode(&index::encode(&ranges)).unwrap(), ranges);
         }
+        assert_eq!(index::decode(&index::encode(&[])).unwrap(), vec![]);
+        assert_eq!(
+            index::decode(&index::encode(&[(0, 0), (2, u64::MAX)])).unwrap(),
+            vec![(0, 0), (2, u64::MAX)]
+        );
+    }
 
-        current_idx += 1;
+    #[test]
+    fn index_rejects_damage() {
+        let bytes = index::encode(&[(3, 5), (10, 20), (1000, 1_000_000)]);
+        // any flipped bit breaks the checksum (or the magic).
+        for pos in 0..bytes.len() {
+            let mut damaged = bytes.clone();
+            damaged[pos] ^= 0x10;
+            assert!(index::decode(&damaged).is_err(), "byte {pos}");
+        }
+        assert!(index::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(index::decode(&bytes[..3]).is_err());
     }
 
-    debug!("Ranges after merging: {:?}", ranges);
+    #[test]
+    fn serve_answers_queries() {
+        let ranges = [(3, 5), (10, 14), (16, 20)];
+        let input = "1\n5\n8\n 11 \r\n17\nabc\n\n-1\n18446744073709551616\n32";
+        let mut output = Vec::new();
+        let queries = serve::answer_queries(&ranges, input.as_bytes(), &mut output).unwrap();
+        assert_eq!(queries, 10);
+        assert_eq!(
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>(),
+            [
+                "spoiled",
+                "fresh",
+                "spoiled",
+                "fresh",
+                "fresh",
+                "error: invalid ingredient ID \"abc\": invalid digit found in string",
+                "error: invalid ingredient ID \"\": cannot parse integer from empty string",
+                "error: invalid ingredient ID \"-1\": invalid digit found in string",
+                "error: invalid ingredient ID \"18446744073709551616\": \
+                 number too large to fit in target type",
+                "spoiled",
+            ]
+        );
+    }
 
-    let get_containing_range = |ranges: &Vec<(u64, u64)>, value: u64| -> Option<usize> {
-        // use binary search to find if a range contains this element.
-        match ranges.binary_search_by(|p| {
-            if value > p.0 && value > p.1 {
-                return Ordering::Less;
-            } else if value < p.0 && value < p.1 {
-                return Ordering::Greater;
-            }
-            Ordering::Equal
-        }) {
-            Ok(val) => Some(val),
-            Err(_) => None,
+    // Hands out one line per read, checking that every earlier line has been
+    // answered first, as an interactive client waits for its answers.
+    struct OneLineAtATime {
+        lines: VecDeque<&'static str>,
+        sent: usize,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for OneLineAtATime {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let answered = self.output.borrow().iter().filter(|&&b| b == b'\n').count();
+            assert_eq!(
+                answered, self.sent,
+                "answers are flushed before the next read"
+            );
+            let Some(line) = self.lines.pop_front() else {
+                return Ok(0);
+            };
+            buf[..line.len()].copy_from_slice(line.as_bytes());
+            self.sent += 1;
+            Ok(line.len())
         }
-    };
+    }
 
-    for range in ranges {
-        // compute the width of this range, and add to our running total of fresh
-        // ingredient ids.
-        solution += range.1 - range.0 + 1;
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn serve_flushes_each_answer() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let input = OneLineAtATime {
+            lines: ["4\n", "12\n", "15\n"].into(),
+            sent: 0,
+            output: output.clone(),
+        };
+        let queries =
+            serve::answer_queries(&[(3, 5), (10, 14)], input, SharedOutput(output.clone()))
+                .unwrap();
+        assert_eq!(queries, 3);
+        assert_eq!(output.borrow().as_slice(), b"fresh\nfresh\nspoiled\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_file_is_reclaimed() {
+        use std::os::unix::net::{UnixListener, UnixStream};
+        let dir = std::env::temp_dir().join(format!("day5-socket-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("serve.sock");
+
+        // a server that is gone leaves its socket file behind.
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let socket = serve::UnixSocket::bind(&path).unwrap();
+
+        // while it listens, the path isn't taken over.
+        let error = serve::UnixSocket::bind(&path).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Unable to listen on {}: another server is listening on it",
+                path.display()
+            )
+        );
+        assert!(UnixStream::connect(&path).is_ok());
+        drop(socket);
+        assert!(!path.exists());
+
+        // nor is anything that isn't a socket.
+        std::fs::write(&path, "data").unwrap();
+        assert!(serve::UnixSocket::bind(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-    Ok(solution)
 }
@@ -0,0 +1,113 @@
+use crate::{DatabaseReader, SourceRange};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug, clap::Args)]
+pub struct TraceArgs {
+    /// Database to load the fresh ranges from
+    input_path: PathBuf,
+    /// Ingredient IDs to trace; the database's own available IDs if none are given
+    ids: Vec<u64>,
+}
+
+// Interval tree over the original, unmerged ranges.
+//
+// The ranges are sorted by start and read as a balanced binary tree: the middle
+// range of a slice is its root and the two halves its subtrees. `max_end` holds
+// the largest end in each subtree (indexed by its root), so a lookup skips every
+// subtree that ends before the ID, and every right subtree that starts after it.
+// A lookup costs O(log n + k) for the k ranges it finds.
+pub struct RangeIndex {
+    ranges: Vec<SourceRange>,
+    max_end: Vec<u64>,
+}
+
+impl RangeIndex {
+    pub fn new(mut ranges: Vec<SourceRange>) -> Self {
+        ranges.sort_by_key(|range| (range.start, range.end, range.line));
+        let mut max_end = vec![0; ranges.len()];
+        fill_max_end(&ranges, &mut max_end, 0, ranges.len());
+        RangeIndex { ranges, max_end }
+    }
+
+    // Every original range containing `value`, in database order.
+    pub fn covering(&self, value: u64) -> Vec<SourceRange> {
+        let mut covering = Vec::new();
+        self.collect_covering(value, 0, self.ranges.len(), &mut covering);
+        covering.sort_by_key(|range| range.line);
+        covering
+    }
+
+    fn collect_covering(&self, value: u64, lo: usize, hi: usize, covering: &mut Vec<SourceRange>) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        if self.max_end[mid] < value {
+            return;
+        }
+        self.collect_covering(value, lo, mid, covering);
+        let range = self.ranges[mid];
+        if range.start <= value {
+            if value <= range.end {
+                covering.push(range);
+            }
+            self.collect_covering(value, mid + 1, hi, covering);
+        }
+    }
+}
+
+// Fills `max_end` for the subtree over `ranges[lo..hi]`, returning its value.
+fn fill_max_end(ranges: &[SourceRange], max_end: &mut [u64], lo: usize, hi: usize) -> u64 {
+    if lo >= hi {
+        return 0;
+    }
+    let mid = lo + (hi - lo) / 2;
+    let left = fill_max_end(ranges, max_end, lo, mid);
+    let right = fill_max_end(ranges, max_end, mid + 1, hi);
+    max_end[mid] = ranges[mid].end.max(left).max(right);
+    max_end[mid]
+}
+
+pub fn trace(args: &TraceArgs) -> Result<()> {
+    let input = File::open(&args.input_path)
+        .with_context(|| format!("Unable to open {}", args.input_path.display()))?;
+    let mut database = DatabaseReader::new(BufReader::new(input));
+    let index = RangeIndex::new(database.ranges()?);
+    info!("Indexed {} fresh ranges", index.ranges.len());
+
+    let ids = if args.ids.is_empty() {
+        database.ids()?
+    } else {
+        args.ids.clone()
+    };
+    let mut output = BufWriter::new(io::stdout().lock());
+    for id in ids {
+        let covering = index.covering(id);
+        if covering.is_empty() {
+            writeln!(output, "{id}: spoiled")?;
+            continue;
+        }
+        let sources: Vec<String> = covering
+            .iter()
+            .map(|range| match range.window.is_always() {
+                true => format!("{}-{} (line {})", range.start, range.end, range.line),
+                false => format!(
+                    "{}-{} @{} (line {})",
+                    range.start, range.end, range.window, range.line
+                ),
+            })
+            .collect();
+        writeln!(
+            output,
+            "{id}: fresh, depth {}: {}",
+            covering.len(),
+            sources.join(", ")
+        )?;
+    }
+    output.flush()?;
+    Ok(())
+}
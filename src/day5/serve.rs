@@ -0,0 +1,184 @@
+use crate::validity::Date;
+use crate::{get_containing_range, read_merged_ranges};
+use anyhow::{bail, ensure, Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    net::{UnixListener, UnixStream},
+};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use tracing::{info, warn};
+
+#[derive(Debug, clap::Args)]
+pub struct ServeArgs {
+    /// Database to load the fresh ranges from; any IDs after them are ignored
+    input_path: PathBuf,
+    /// Answer connections on `tcp:HOST:PORT` or `unix:PATH` instead of stdin
+    #[arg(long)]
+    listen: Option<Listen>,
+    /// Only answer from the ranges fresh on this date (YYYY-MM-DD)
+    #[arg(long)]
+    as_of: Option<Date>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Listen {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for Listen {
+    type Err = anyhow::Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        match address.split_once(':') {
+            Some(("tcp", address)) => Ok(Listen::Tcp(address.to_string())),
+            Some(("unix", path)) => Ok(Listen::Unix(PathBuf::from(path))),
+            _ => bail!("Expected tcp:HOST:PORT or unix:PATH, got: {address}"),
+        }
+    }
+}
+
+// The protocol is the same on stdin and on every connection: each line holds an
+// ingredient ID, and gets a line back with `fresh`, `spoiled`, or `error: ...`
+// if it isn't an ID. Answers are flushed whenever the pending input runs out,
+// so a client can send one ID at a time or stream millions of them.
+pub fn answer_queries(ranges: &[(u64, u64)], input: impl Read, output: impl Write) -> Result<u64> {
+    let mut input = BufReader::new(input);
+    let mut output = BufWriter::new(output);
+    let mut queries = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        queries += 1;
+
+        match line.trim().parse::<u64>() {
+            Ok(value) if get_containing_range(ranges, value).is_some() => {
+                writeln!(output, "fresh")?
+            }
+            Ok(_) => writeln!(output, "spoiled")?,
+            Err(error) => writeln!(
+                output,
+                "error: invalid ingredient ID {:?}: {error}",
+                line.trim()
+            )?,
+        }
+        if input.buffer().is_empty() {
+            output.flush()?;
+        }
+    }
+    output.flush()?;
+    Ok(queries)
+}
+
+// Answers each connection on its own thread, all sharing the loaded ranges.
+fn serve_connections<S: Read + Write + Send + 'static>(
+    ranges: Arc<Vec<(u64, u64)>>,
+    connections: impl Iterator<Item = io::Result<S>>,
+    try_clone: fn(&S) -> io::Result<S>,
+) -> Result<()> {
+    for connection in connections {
+        let connection = connection?;
+        let ranges = ranges.clone();
+        let reader = try_clone(&connection)?;
+        thread::spawn(move || match answer_queries(&ranges, reader, connection) {
+            Ok(queries) => info!("Answered {queries} queries"),
+            Err(error) => warn!("Connection failed: {error:#}"),
+        });
+    }
+    Ok(())
+}
+
+pub fn serve(args: &ServeArgs) -> Result<()> {
+    let input = File::open(&args.input_path)
+        .with_context(|| format!("Unable to open {}", args.input_path.display()))?;
+    let ranges = read_merged_ranges(&mut BufReader::new(input), args.as_of)?;
+    info!("Loaded {} merged fresh ranges", ranges.len());
+
+    match &args.listen {
+        None => {
+            let queries = answer_queries(&ranges, io::stdin().lock(), io::stdout().lock())?;
+            info!("Answered {queries} queries");
+        }
+        Some(Listen::Tcp(address)) => {
+            let listener = TcpListener::bind(address)
+                .with_context(|| format!("Unable to listen on {address}"))?;
+            info!("Listening on tcp:{}", listener.local_addr()?);
+            serve_connections(Arc::new(ranges), listener.incoming(), |stream| {
+                stream.try_clone()
+            })?;
+        }
+        #[cfg(unix)]
+        Some(Listen::Unix(path)) => {
+            let socket = UnixSocket::bind(path)?;
+            // the guard can't run when the server is interrupted, so the
+            // handler removes the socket file itself.
+            let socket_path = path.clone();
+            ctrlc::set_handler(move || {
+                let _ = fs::remove_file(&socket_path);
+                std::process::exit(130);
+            })
+            .context("Unable to handle Ctrl-C")?;
+            info!("Listening on unix:{}", path.display());
+            serve_connections(Arc::new(ranges), socket.listener.incoming(), |stream| {
+                stream.try_clone()
+            })?;
+        }
+        #[cfg(not(unix))]
+        Some(Listen::Unix(_)) => bail!("Unix sockets are not supported on this platform"),
+    }
+    Ok(())
+}
+
+// A listening Unix socket. Its file is removed when this is dropped, so the
+// next server can bind the same path.
+#[cfg(unix)]
+pub struct UnixSocket {
+    pub listener: UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    // Binds `path`, first removing a socket file left behind by a server that
+    // is no longer running. Anything else at `path` is left alone.
+    pub fn bind(path: &Path) -> Result<Self> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            ensure!(
+                metadata.file_type().is_socket(),
+                "Unable to listen on {}: it exists and is not a socket",
+                path.display()
+            );
+            ensure!(
+                UnixStream::connect(path).is_err(),
+                "Unable to listen on {}: another server is listening on it",
+                path.display()
+            );
+            info!("Removing stale socket {}", path.display());
+            fs::remove_file(path)
+                .with_context(|| format!("Unable to remove {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Unable to listen on {}", path.display()))?;
+        Ok(UnixSocket {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
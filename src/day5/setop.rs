@@ -0,0 +1,107 @@
+use crate::{count_ids, read_merged_ranges};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SetOperation {
+    /// IDs fresh in any database
+    Union,
+    /// IDs fresh in every database
+    Intersection,
+    /// IDs fresh in the first database and in none of the others
+    Difference,
+    /// IDs fresh in an odd number of databases
+    SymmetricDifference,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SetopArgs {
+    operation: SetOperation,
+    /// Databases to combine, in the day 5 format; only their ranges are read
+    #[arg(num_args = 2.., required = true)]
+    input_paths: Vec<PathBuf>,
+}
+
+impl SetOperation {
+    // Whether an ID is in the result, given which databases it is fresh in.
+    fn includes(&self, fresh_in: &[bool]) -> bool {
+        match self {
+            SetOperation::Union => fresh_in.iter().any(|&fresh| fresh),
+            SetOperation::Intersection => fresh_in.iter().all(|&fresh| fresh),
+            SetOperation::Difference => fresh_in[0] && !fresh_in[1..].iter().any(|&fresh| fresh),
+            SetOperation::SymmetricDifference => {
+                fresh_in.iter().filter(|&&fresh| fresh).count() % 2 == 1
+            }
+        }
+    }
+}
+
+// Applies `operation` to sets of merged ranges, returning the merged ranges of
+// the result. Sweeps over the points where any set starts or stops covering
+// IDs; between two such points membership can't change. The result is
+// canonical: ranges are disjoint, sorted, and ranges that touch are joined.
+pub fn combine(operation: SetOperation, sets: &[Vec<(u64, u64)>]) -> Vec<(u64, u64)> {
+    // (first ID, set, whether the set covers IDs from there on). Ends are kept
+    // one past the range, as u128 so that a range ending at u64::MAX fits.
+    let mut boundaries: Vec<(u128, usize, bool)> = sets
+        .iter()
+        .enumerate()
+        .flat_map(|(set, ranges)| {
+            ranges.iter().flat_map(move |&(start, end)| {
+                [(start as u128, set, true), (end as u128 + 1, set, false)]
+            })
+        })
+        .collect();
+    boundaries.sort_unstable();
+
+    let mut fresh_in = vec![false; sets.len()];
+    let mut result = Vec::new();
+    let mut result_start = None;
+    let mut idx = 0;
+    while idx < boundaries.len() {
+        // apply every boundary at this ID before checking membership.
+        let point = boundaries[idx].0;
+        while idx < boundaries.len() && boundaries[idx].0 == point {
+            let (_, set, covers) = boundaries[idx];
+            fresh_in[set] = covers;
+            idx += 1;
+        }
+
+        match (result_start, operation.includes(&fresh_in)) {
+            (None, true) => result_start = Some(point),
+            (Some(start), false) => {
+                result.push((start as u64, (point - 1) as u64));
+                result_start = None;
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+pub fn setop(args: &SetopArgs) -> Result<()> {
+    let sets = args
+        .input_paths
+        .iter()
+        .map(|path| {
+            let input =
+                File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+            read_merged_ranges(&mut BufReader::new(input), None)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let result = combine(args.operation, &sets);
+    info!("{:?} of {} databases", args.operation, sets.len());
+
+    let mut output = BufWriter::new(io::stdout().lock());
+    for (start, end) in &result {
+        writeln!(output, "{start}-{end}")?;
+    }
+    output.flush()?;
+    // the ranges alone on stdout stay usable as a database.
+    eprintln!("{} IDs in {} ranges", count_ids(&result), result.len());
+    Ok(())
+}
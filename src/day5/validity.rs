@@ -0,0 +1,169 @@
+use crate::setop::{combine, SetOperation};
+use crate::{count_ids, ranges_as_of, DatabaseReader};
+use anyhow::{bail, ensure, Context, Result};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, clap::Args)]
+pub struct DiffArgs {
+    /// Database with validity windows on its ranges
+    input_path: PathBuf,
+    /// Date to compare from, as YYYY-MM-DD
+    from: Date,
+    /// Date to compare to, as YYYY-MM-DD
+    to: Date,
+}
+
+// A calendar date, ordered chronologically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl FromStr for Date {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let parts: Vec<&str> = text.split('-').collect();
+        let [year, month, day] = parts[..] else {
+            bail!("Expected a date like 2025-12-01, got: {text}");
+        };
+        ensure!(
+            year.len() == 4 && month.len() == 2 && day.len() == 2,
+            "Expected a date like 2025-12-01, got: {text}"
+        );
+        let date = Date {
+            year: year
+                .parse()
+                .with_context(|| format!("Invalid year in {text}"))?,
+            month: month
+                .parse()
+                .with_context(|| format!("Invalid month in {text}"))?,
+            day: day
+                .parse()
+                .with_context(|| format!("Invalid day in {text}"))?,
+        };
+        ensure!(
+            (1..=12).contains(&date.month),
+            "No month {} in {text}",
+            date.month
+        );
+        ensure!(
+            (1..=date.days_in_month()).contains(&date.day),
+            "No day {} in {text}",
+            date.day
+        );
+        Ok(date)
+    }
+}
+
+impl Date {
+    fn days_in_month(&self) -> u8 {
+        let leap = self.year.is_multiple_of(4)
+            && (!self.year.is_multiple_of(100) || self.year.is_multiple_of(400));
+        match self.month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+// The dates a range is fresh on, written `FROM..UNTIL` after an `@`. Both ends
+// are included, and either may be left out to leave that side open. A range
+// without a window is fresh on every date.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Validity {
+    from: Option<Date>,
+    until: Option<Date>,
+}
+
+impl Validity {
+    pub fn is_always(&self) -> bool {
+        self.from.is_none() && self.until.is_none()
+    }
+
+    pub fn contains(&self, date: Date) -> bool {
+        self.from.is_none_or(|from| from <= date) && self.until.is_none_or(|until| date <= until)
+    }
+}
+
+impl FromStr for Validity {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let Some((from, until)) = text.split_once("..") else {
+            bail!("Expected a validity window like 2025-12-01..2025-12-24, got: {text}");
+        };
+        let parse = |date: &str| (!date.is_empty()).then(|| date.parse()).transpose();
+        let validity = Validity {
+            from: parse(from)?,
+            until: parse(until)?,
+        };
+        if let (Some(from), Some(until)) = (validity.from, validity.until) {
+            ensure!(
+                from <= until,
+                "Validity window {text} ends before it starts"
+            );
+        }
+        Ok(validity)
+    }
+}
+
+impl fmt::Display for Validity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(from) = self.from {
+            write!(f, "{from}")?;
+        }
+        write!(f, "..")?;
+        if let Some(until) = self.until {
+            write!(f, "{until}")?;
+        }
+        Ok(())
+    }
+}
+
+// Prints the ranges that became fresh (`+`) or stopped being fresh (`-`)
+// between the two dates, in ID order.
+pub fn diff(args: &DiffArgs) -> Result<()> {
+    let input = File::open(&args.input_path)
+        .with_context(|| format!("Unable to open {}", args.input_path.display()))?;
+    let ranges = DatabaseReader::new(BufReader::new(input)).ranges()?;
+    let before = ranges_as_of(&ranges, Some(args.from));
+    let after = ranges_as_of(&ranges, Some(args.to));
+
+    let gained = combine(SetOperation::Difference, &[after.clone(), before.clone()]);
+    let lost = combine(SetOperation::Difference, &[before, after]);
+    let mut changes: Vec<(char, (u64, u64))> = gained
+        .iter()
+        .map(|&range| ('+', range))
+        .chain(lost.iter().map(|&range| ('-', range)))
+        .collect();
+    changes.sort_by_key(|&(_, range)| range);
+
+    let mut output = BufWriter::new(io::stdout().lock());
+    for (sign, (start, end)) in changes {
+        writeln!(output, "{sign}{start}-{end}")?;
+    }
+    output.flush()?;
+    eprintln!(
+        "From {} to {}: {} IDs became fresh, {} IDs stopped being fresh",
+        args.from,
+        args.to,
+        count_ids(&gained),
+        count_ids(&lost)
+    );
+    Ok(())
+}
@@ -32,96 +32,167 @@ Of course, the actual worksheet is much wider. You'll need to make sure to unrol
 Solve the problems on the math worksheet. What is the grand total found by adding together all of the answers to the individual problems?
 */
 
-use anyhow::Result;
-use regex::Regex;
-use shared::shared_main;
+mod worksheet;
+
+use anyhow::{bail, Context, Result};
+use shared::shared_main_with_options;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
-use tracing::{debug, info, trace};
+use std::io::{self, BufReader, BufWriter, Write};
+use tracing::{debug, info};
+use worksheet::{Problem, Worksheet};
 
 fn main() -> Result<()> {
-    shared_main(solution1, solution2)
+    shared_main_with_options(solution1, solution2)
+}
+
+#[derive(Debug, clap::Args)]
+struct Options {
+    /// Print each problem's operands as read, its operator, its result and the
+    /// columns it spans in the sheet
+    #[arg(long)]
+    report: bool,
 }
 
+// The operators a problem can use, by their symbol in the operator row. All
+// arithmetic is checked, so a problem overflowing a u64 (or going below 0, or
+// dividing by 0) is an error rather than a wrong total.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Operator {
+    // `*`
     Multiply,
+    // `+`
     Add,
+    // `-`
+    Subtract,
+    // `/`, rounding down
+    Divide,
+    // `^`
+    Power,
+    // `<`, the smallest operand
+    Min,
+    // `>`, the largest operand
+    Max,
+    // `|`, joining the decimal digits: 12 | 34 = 1234
+    Concatenate,
 }
 
-fn solution1(input: &File) -> Result<u64> {
-    let number_regex = Regex::new(r"^\s*\d+(?:\s+\d+)*\s*$")?;
-    let number_extract_regex = Regex::new(r"\d+")?;
-    let operation_regex = Regex::new(r"^\s*[+*](?:\s+[+*])*\s*$")?;
-    let operation_extract_regex = Regex::new(r"[+*]")?;
-    let mut solution = 0;
-
-    let mut input_reader = BufReader::new(input);
-    let mut operands = Vec::new();
-    let mut operators = Vec::new();
-    let mut row_width = 0;
-
-    loop {
-        let mut input_buffer = Vec::new();
-        let delim = b'\n';
-        let bytes_read = input_reader.read_until(delim, &mut input_buffer)?;
-        if bytes_read == 0 {
-            break;
+impl Operator {
+    fn from_symbol(symbol: u8) -> Option<Self> {
+        match symbol {
+            b'*' => Some(Operator::Multiply),
+            b'+' => Some(Operator::Add),
+            b'-' => Some(Operator::Subtract),
+            b'/' => Some(Operator::Divide),
+            b'^' => Some(Operator::Power),
+            b'<' => Some(Operator::Min),
+            b'>' => Some(Operator::Max),
+            b'|' => Some(Operator::Concatenate),
+            _ => None,
         }
+    }
 
-        let input_str = String::from_utf8(input_buffer)?;
-        debug!("Input line: {input_str}");
-        if number_regex.is_match(&input_str) {
-            for capture in number_extract_regex.find_iter(&input_str) {
-                debug!("Capture: {}", capture.as_str());
-                operands.push(capture.as_str().parse::<u64>()?);
-            }
-        } else if operation_regex.is_match(&input_str) {
-            for capture in operation_extract_regex.find_iter(&input_str) {
-                debug!("Capture: {}", capture.as_str());
-                operators.push(match capture.as_str() {
-                    "+" => Operator::Add,
-                    "*" => Operator::Multiply,
-                    err_val => panic!("Unable to parse operand: {err_val}"),
-                });
-            }
-        } else {
-            panic!("Invald input format: {input_str}");
+    fn symbol(&self) -> char {
+        match self {
+            Operator::Multiply => '*',
+            Operator::Add => '+',
+            Operator::Subtract => '-',
+            Operator::Divide => '/',
+            Operator::Power => '^',
+            Operator::Min => '<',
+            Operator::Max => '>',
+            Operator::Concatenate => '|',
         }
     }
 
-    row_width = operators.len();
-
-    info!("Using grid row width of: {row_width}");
-
-    let compute_problem = |grid: Vec<u64>, operators: Vec<Operator>, width| {
-        let mut column_solutions = vec![0; width];
-        for y in 0..grid.len() / width {
-            for x in 0..width {
-                match y {
-                    0 => column_solutions[x] = grid[y * width + x],
-                    _ => match operators[x] {
-                        Operator::Add => {
-                            column_solutions[x] += grid[y * width + x];
-                        }
-                        Operator::Multiply => {
-                            column_solutions[x] *= grid[y * width + x];
-                        }
-                    },
-                }
-            }
+    fn apply(&self, a: u64, b: u64) -> Result<u64> {
+        let result = match self {
+            Operator::Multiply => a.checked_mul(b),
+            Operator::Add => a.checked_add(b),
+            Operator::Subtract => a.checked_sub(b),
+            Operator::Divide => a.checked_div(b),
+            Operator::Power => match u32::try_from(b) {
+                Ok(b) => a.checked_pow(b),
+                // only 0 and 1 have powers this large that fit
+                Err(_) => (a <= 1).then_some(a),
+            },
+            Operator::Min => Some(a.min(b)),
+            Operator::Max => Some(a.max(b)),
+            Operator::Concatenate => 10u64
+                .checked_pow(b.checked_ilog10().unwrap_or(0) + 1)
+                .and_then(|shift| a.checked_mul(shift))
+                .and_then(|a| a.checked_add(b)),
+        };
+        match result {
+            Some(result) => Ok(result),
+            None if *self == Operator::Divide => bail!("{a} / {b} divides by zero"),
+            None => bail!("{a} {} {b} is out of range", self.symbol()),
         }
-        column_solutions
-    };
+    }
 
-    let column_solutions = compute_problem(operands, operators, row_width);
-    info!("column solutions:\n{:?}", column_solutions);
-    for column in column_solutions {
-        solution += column
+    // Combines the operands in the order they are read, from the left: `a b c`
+    // under `-` is (a - b) - c, and under `^` is (a ^ b) ^ c.
+    fn fold(&self, operands: &[u64]) -> Result<u64> {
+        let (&first, rest) = operands.split_first().context("No operands to fold")?;
+        rest.iter()
+            .try_fold(first, |total, &value| self.apply(total, value))
     }
+}
 
+// Writes one line of the report, e.g. `columns 1-3: 123 * 45 * 6 = 33210`.
+fn report_problem(
+    output: &mut impl Write,
+    problem: &Problem,
+    operands: &[u64],
+    result: u64,
+) -> Result<()> {
+    let operands: Vec<String> = operands.iter().map(u64::to_string).collect();
+    writeln!(
+        output,
+        "{}: {} = {result}",
+        problem.span(),
+        operands.join(&format!(" {} ", problem.operator.symbol()))
+    )?;
+    Ok(())
+}
+
+// Sums the results of the problems, taken in the order given and each reading
+// its operands with `operands`. Every problem folds from its first operand, so
+// a 0 anywhere among them is just another operand.
+fn grand_total<'a>(
+    problems: impl Iterator<Item = &'a Problem>,
+    operands: fn(&Problem) -> Result<Vec<u64>>,
+    options: &Options,
+) -> Result<u64> {
+    let mut solution = 0u64;
+    let mut report = BufWriter::new(io::stdout().lock());
+
+    for problem in problems {
+        let operands = operands(problem)?;
+        let total = problem
+            .operator
+            .fold(&operands)
+            .with_context(|| format!("Problem at {}", problem.span()))?;
+        debug!("{:?} of {:?} = {total}", problem.operator, operands);
+        if options.report {
+            report_problem(&mut report, problem, &operands, total)?;
+        }
+        solution = solution
+            .checked_add(total)
+            .context("The grand total is out of range")?;
+    }
+
+    report.flush()?;
     Ok(solution)
 }
 
+fn solution1(input: &File, options: &Options) -> Result<u64> {
+    let worksheet = Worksheet::read(BufReader::new(input))?;
+    info!("Read {} problems", worksheet.problems.len());
+
+    // read the numbers of each problem along its rows, top to bottom.
+    grand_total(worksheet.problems.iter(), Problem::row_operands, options)
+}
+
 /*
 --- Part Two ---
 
@@ -148,105 +219,185 @@ Now, the grand total is 1058 + 3253600 + 625 + 8544 = 3263827.
 Solve the problems on the math worksheet again. What is the grand total found by adding together all of the answers to the individual problems?
 */
 
-fn solution2(input: &File) -> Result<u64> {
-    let mut solution = 0;
-
-    let mut input_reader = BufReader::new(input);
-    let mut buf = Vec::new();
+fn solution2(input: &File, options: &Options) -> Result<u64> {
+    let worksheet = Worksheet::read(BufReader::new(input))?;
+    info!("Read {} problems", worksheet.problems.len());
 
-    let delim = b'\n';
-    let mut row_width = 0;
-    loop {
-        let mut line_buf = Vec::new();
-        let bytes = input_reader.read_until(delim, &mut line_buf)?;
+    // read the problems right-to-left, each a column at a time.
+    grand_total(
+        worksheet.problems.iter().rev(),
+        Problem::column_operands,
+        options,
+    )
+}
 
-        if bytes == 0 {
-            break;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const EXAMPLE: &str = "123 328  51 64 \n 45 64  387 23 \n  6 98  215 314\n*   +   *   +  ";
+
+    #[test]
+    fn worksheet_views() {
+        for text in [
+            EXAMPLE.to_string(),
+            EXAMPLE.replace('\n', "\r\n") + "\r\n\r\n",
+        ] {
+            let worksheet = Worksheet::read(Cursor::new(text)).unwrap();
+            let spans: Vec<_> = worksheet
+                .problems
+                .iter()
+                .map(|p| p.columns.clone())
+                .collect();
+            assert_eq!(spans, vec![0..3, 4..7, 8..11, 12..15]);
+            assert_eq!(worksheet.problems[1].rows, vec!["328", "64 ", "98 "]);
+            let rows: Vec<_> = worksheet
+                .problems
+                .iter()
+                .map(|p| p.row_operands().unwrap())
+                .collect();
+            assert_eq!(rows[0], vec![123, 45, 6]);
+            assert_eq!(rows[3], vec![64, 23, 314]);
+            let columns: Vec<_> = worksheet
+                .problems
+                .iter()
+                .map(|p| p.column_operands().unwrap())
+                .collect();
+            assert_eq!(columns[0], vec![356, 24, 1]);
+            assert_eq!(columns[3], vec![4, 431, 623]);
+            let operators: Vec<_> = worksheet.problems.iter().map(|p| p.operator).collect();
+            use Operator::*;
+            assert_eq!(operators, vec![Multiply, Add, Multiply, Add]);
         }
 
-        if line_buf.last() == Some(&delim) {
-            line_buf.pop();
-        }
+        // a row can hold two numbers in one block, which only reads by columns.
+        let worksheet = Worksheet::read(Cursor::new("1 2\n345\n+  ")).unwrap();
+        assert_eq!(worksheet.problems.len(), 1);
+        assert!(worksheet.problems[0].row_operands().is_err());
+        assert_eq!(
+            worksheet.problems[0].column_operands().unwrap(),
+            vec![25, 4, 13]
+        );
+    }
 
-        row_width = line_buf.len();
-        buf.append(&mut line_buf);
+    #[test]
+    fn operators_are_checked() {
+        use Operator::*;
+        assert_eq!(Subtract.apply(5, 3).unwrap(), 2);
+        assert!(Subtract.apply(3, 5).is_err());
+        assert_eq!(Divide.apply(7, 2).unwrap(), 3);
+        let error = Divide.apply(7, 0).unwrap_err();
+        assert!(error.to_string().contains("divides by zero"));
+        assert_eq!(Power.apply(2, 63).unwrap(), 1 << 63);
+        assert!(Power.apply(2, 64).is_err());
+        assert!(Power.apply(2, 1 << 40).is_err());
+        assert_eq!(Power.apply(1, 1 << 40).unwrap(), 1);
+        assert!(Add.apply(u64::MAX, 1).is_err());
+        assert!(Multiply.apply(1 << 32, 1 << 32).is_err());
+        assert_eq!(Concatenate.apply(12, 34).unwrap(), 1234);
+        assert_eq!(Concatenate.apply(12, 0).unwrap(), 120);
+        assert_eq!(Concatenate.apply(0, 7).unwrap(), 7);
+        assert_eq!(Concatenate.apply(u64::MAX / 10, 5).unwrap(), u64::MAX);
+        assert!(Concatenate.apply(u64::MAX / 10, 6).is_err());
+        assert_eq!(Min.fold(&[4, 2, 9]).unwrap(), 2);
+        assert_eq!(Max.fold(&[4, 2, 9]).unwrap(), 9);
+        assert_eq!(Subtract.fold(&[7]).unwrap(), 7);
+        assert!(Add.fold(&[]).is_err());
     }
 
-    info!("Using grid row width of: {row_width}");
-    debug!("Grid: {:?}", buf);
-
-    let compute_problem = |grid: Vec<u8>, width| {
-        let mut problem_solutions = Vec::new();
-        let height = grid.len() / width;
-        let mut values = Vec::new();
-        let mut num_values = 0;
-        let mut operator = Operator::Add;
-        for x in (0..width).rev() {
-            let mut column_has_value = false;
-            let mut value: u64 = 0;
-            for y in 0..height {
-                trace!("Processing character: {}", grid[y * width + x] as char);
-                match grid[y * width + x] {
-                    b'\n' => continue,
-                    b'+' => {
-                        operator = Operator::Add;
-                        debug!("Setting operator to +");
-                    }
-                    b'*' => {
-                        operator = Operator::Multiply;
-                        debug!("Setting operator to *");
-                    }
-                    val => {
-                        if val.is_ascii_digit() {
-                            value = value * 10 + (val - b'0') as u64;
-                            column_has_value = true;
-                            trace!("Appended {val} to column value: {value}");
-                        }
-                    }
-                };
-            }
-            if !column_has_value || x == 0 {
-                if x == 0 {
-                    debug!("Adding final value: {value} to list of operands");
-                    values.push(value);
-                }
-                debug!(
-                    "Processing list of operands: {:?} with operator {}",
-                    values,
-                    match operator {
-                        Operator::Add => "+",
-                        Operator::Multiply => "*",
-                    }
-                );
-                if num_values > 0 {
-                    let mut total = 0u64;
-                    for &value in &values {
-                        if total == 0 {
-                            total = value;
-                        } else {
-                            match operator {
-                                Operator::Add => total += value,
-                                Operator::Multiply => total *= value,
-                            }
-                        }
-                    }
-                    problem_solutions.push(total);
-                    values.clear();
-                }
-            } else {
-                debug!("Adding value: {value} to list of operands");
-                values.push(value);
-                num_values += 1;
-            }
-        }
-        problem_solutions
-    };
+    #[test]
+    fn operators_fold_in_reading_order() {
+        let results = |text: &str| {
+            let worksheet = Worksheet::read(Cursor::new(text)).unwrap();
+            let fold = |operands: Vec<u64>, problem: &Problem| problem.operator.fold(&operands);
+            let rows: Vec<u64> = worksheet
+                .problems
+                .iter()
+                .map(|p| fold(p.row_operands().unwrap(), p).unwrap())
+                .collect();
+            let columns: Vec<u64> = worksheet
+                .problems
+                .iter()
+                .map(|p| fold(p.column_operands().unwrap(), p).unwrap())
+                .collect();
+            (rows, columns)
+        };
+        // rows: (20 - 5) - 3, (10 / 3) / 1, (2 ^ 3) ^ 2
+        // columns, right to left: 53 - 2, 31 / 1, 232
+        let (rows, columns) = results("20 10 2\n 5  3 3\n 3  1 2\n-  /  ^");
+        assert_eq!(rows, vec![12, 3, 64]);
+        assert_eq!(columns, vec![51, 31, 232]);
+        let (rows, columns) = results("12 7 3\n 4 8 5\n<  > |");
+        assert_eq!(rows, vec![4, 8, 35]);
+        assert_eq!(columns, vec![1, 78, 35]);
+    }
 
-    let column_solutions = compute_problem(buf, row_width);
-    info!("column solutions:\n{:?}", column_solutions);
-    for column in column_solutions {
-        solution += column
+    // Grand totals of both parts for a sheet.
+    fn grand_totals(text: &str) -> (Result<u64>, Result<u64>) {
+        let worksheet = Worksheet::read(Cursor::new(text)).unwrap();
+        let options = Options { report: false };
+        (
+            grand_total(worksheet.problems.iter(), Problem::row_operands, &options),
+            grand_total(
+                worksheet.problems.iter().rev(),
+                Problem::column_operands,
+                &options,
+            ),
+        )
     }
 
-    Ok(solution)
+    #[test]
+    fn folds_seed_from_first_operand() {
+        let totals = |text| {
+            let (rows, columns) = grand_totals(text);
+            (rows.unwrap(), columns.unwrap())
+        };
+        assert_eq!(totals(EXAMPLE), (4277556, 3263827));
+
+        // zero operands, read right to left as 0 * 5 * 3 and 4 * 0 * 6
+        assert_eq!(totals("350\n*  "), (350, 0));
+        assert_eq!(totals("604\n*  "), (604, 0));
+        assert_eq!(totals("0\n0\n*"), (0, 0));
+        assert_eq!(totals("0 5\n7 0\n* +"), (5, 57));
+        // 5 - 5 leaves 0, which has to stay the running total
+        assert!(grand_totals("355\n-  ").1.is_err());
+        assert_eq!(totals("9\n9\n0\n-"), (0, 990));
+
+        // single operands
+        assert_eq!(totals("7\n*"), (7, 7));
+        assert_eq!(totals("7 12\n+ * "), (19, 9));
+
+        // problems touching both edges of the sheet, without padding
+        assert_eq!(totals("1 2\n3 4\n+ *"), (12, 37));
+        assert_eq!(totals("12 3\n 4 5\n+  |"), (51, 60));
+    }
+
+    #[test]
+    fn worksheet_rejects_malformed() {
+        for (text, message) in [
+            ("", "The worksheet is empty"),
+            ("*   +\n", "no rows of numbers"),
+            (
+                "12 3\n45 6\n",
+                "Line 2: the last row has to hold the operators",
+            ),
+            ("12 3\n4 5\n*  +", "Line 2 is 3 characters wide"),
+            ("12 3 \n45 6 \n*  +", "Line 1 is 5 characters wide"),
+            ("12 3\n45 6\n*   ", "columns 4-4 has 0 operators"),
+            ("123 4\n456 5\n*+  +", "columns 1-3 has 2 operators"),
+            (
+                "12 3\n+5 6\n*  +",
+                "Line 2: operators are only allowed on the last row",
+            ),
+            ("12 3\n45 x\n*  +", "Line 2: unexpected character 'x'"),
+            ("1   3\n4   6\n* + +", "columns 3-3 has no numbers"),
+        ] {
+            let error = match Worksheet::read(Cursor::new(text)) {
+                Ok(_) => panic!("{text:?} was accepted"),
+                Err(error) => format!("{error:#}"),
+            };
+            assert!(error.contains(message), "{text:?}: {error}");
+        }
+    }
 }
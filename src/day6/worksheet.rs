@@ -0,0 +1,172 @@
+use crate::Operator;
+use anyhow::{bail, ensure, Context, Result};
+use std::io::BufRead;
+use std::ops::Range;
+
+// A math worksheet: rows of numbers over a row of operators, with the problems
+// side by side and separated by columns holding only spaces. Every row has to
+// be the same width, so the columns line up.
+pub struct Worksheet {
+    pub problems: Vec<Problem>,
+}
+
+// One problem's block of the sheet.
+pub struct Problem {
+    // columns of the sheet the problem spans, 0-based
+    pub columns: Range<usize>,
+    // the problem's number rows as written, each as wide as the problem
+    pub rows: Vec<String>,
+    pub operator: Operator,
+}
+
+impl Worksheet {
+    pub fn read(input: impl BufRead) -> Result<Self> {
+        let mut lines = Vec::new();
+        for (idx, line) in input.lines().enumerate() {
+            let mut line = line.with_context(|| format!("Unable to read line {}", idx + 1))?;
+            if line.ends_with('\r') {
+                line.pop();
+            }
+            if let Some(c) = line
+                .bytes()
+                .find(|&c| c != b' ' && !c.is_ascii_digit() && Operator::from_symbol(c).is_none())
+            {
+                bail!("Line {}: unexpected character {:?}", idx + 1, c as char);
+            }
+            lines.push(line);
+        }
+        while lines.last().is_some_and(|line| line.trim().is_empty()) {
+            lines.pop();
+        }
+
+        let Some(operator_row) = lines.pop() else {
+            bail!("The worksheet is empty");
+        };
+        ensure!(
+            !operator_row.bytes().any(|c| c.is_ascii_digit()),
+            "Line {}: the last row has to hold the operators, got {operator_row:?}",
+            lines.len() + 1
+        );
+        ensure!(
+            !lines.is_empty(),
+            "The worksheet has no rows of numbers above its operators"
+        );
+        let width = operator_row.len();
+        for (idx, row) in lines.iter().enumerate() {
+            ensure!(
+                !row.bytes().any(|c| Operator::from_symbol(c).is_some()),
+                "Line {}: operators are only allowed on the last row",
+                idx + 1
+            );
+            ensure!(
+                row.len() == width,
+                "Line {} is {} characters wide, but the operator row is {width}; \
+                 every row has to be the same width",
+                idx + 1,
+                row.len()
+            );
+        }
+
+        // split the sheet on the columns holding only spaces.
+        let is_separator = |x: usize| {
+            operator_row.as_bytes()[x] == b' ' && lines.iter().all(|row| row.as_bytes()[x] == b' ')
+        };
+        let mut problems = Vec::new();
+        let mut start = None;
+        for x in 0..=width {
+            match (start, x == width || is_separator(x)) {
+                (None, false) => start = Some(x),
+                (Some(first), true) => {
+                    problems.push(Problem::new(first..x, &lines, &operator_row)?);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        ensure!(!problems.is_empty(), "The worksheet has no problems");
+        Ok(Worksheet { problems })
+    }
+}
+
+impl Problem {
+    fn new(columns: Range<usize>, lines: &[String], operator_row: &str) -> Result<Self> {
+        let span = format!("columns {}-{}", columns.start + 1, columns.end);
+        let operators: Vec<Operator> = operator_row.as_bytes()[columns.clone()]
+            .iter()
+            .filter_map(|&c| Operator::from_symbol(c))
+            .collect();
+        let [operator] = operators[..] else {
+            bail!(
+                "The problem at {span} has {} operators, instead of one",
+                operators.len()
+            );
+        };
+        let rows: Vec<String> = lines
+            .iter()
+            .map(|line| line[columns.clone()].to_string())
+            .collect();
+        ensure!(
+            rows.iter()
+                .any(|row| row.bytes().any(|c| c.is_ascii_digit())),
+            "The problem at {span} has no numbers"
+        );
+        Ok(Problem {
+            columns,
+            rows,
+            operator,
+        })
+    }
+
+    // The columns of the sheet the problem spans, as written in messages.
+    pub fn span(&self) -> String {
+        format!("columns {}-{}", self.columns.start + 1, self.columns.end)
+    }
+
+    // The problem's operands read a row at a time, top to bottom. Each row holds
+    // at most one number.
+    pub fn row_operands(&self) -> Result<Vec<u64>> {
+        let mut operands = Vec::new();
+        for (idx, row) in self.rows.iter().enumerate() {
+            let numbers: Vec<&str> = row.split_whitespace().collect();
+            match numbers[..] {
+                [] => {}
+                [number] => operands.push(
+                    number
+                        .parse()
+                        .with_context(|| format!("Line {}: {number} is too large", idx + 1))?,
+                ),
+                _ => bail!(
+                    "Line {}: the problem at {} holds {} numbers on one row",
+                    idx + 1,
+                    self.span(),
+                    numbers.len()
+                ),
+            }
+        }
+        Ok(operands)
+    }
+
+    // The problem's operands read a column at a time, right to left, each with
+    // its most significant digit at the top.
+    pub fn column_operands(&self) -> Result<Vec<u64>> {
+        let mut operands = Vec::new();
+        for x in (0..self.columns.len()).rev() {
+            let digits: String = self
+                .rows
+                .iter()
+                .map(|row| row.as_bytes()[x] as char)
+                .filter(char::is_ascii_digit)
+                .collect();
+            if digits.is_empty() {
+                continue;
+            }
+            operands.push(digits.parse().with_context(|| {
+                format!(
+                    "Column {}: {digits} is too large",
+                    self.columns.start + x + 1
+                )
+            })?);
+        }
+        Ok(operands)
+    }
+}
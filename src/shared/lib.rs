@@ -1,17 +1,44 @@
 use anyhow::{Context, Result};
 use clap::Parser;
 use displaydoc::Display;
-use std::{fs::File, path::PathBuf};
+use std::{
+    fmt::Debug,
+    fs::File,
+    path::{Path, PathBuf},
+};
 use tracing::info;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
+pub mod test_util;
+
 // Define a struct to hold the command-line arguments
 #[derive(Debug, Parser, Display)]
-struct Args {
+struct Args<O: clap::Args> {
     solution: u32,
     input_path: PathBuf,
+    #[command(flatten)]
+    options: O,
+}
+
+// Arguments for days that also offer subcommands: either a solution and input
+// path as usual, or one of the day's commands `C` on its own.
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct CommandArgs<O: clap::Args, C: clap::Subcommand> {
+    #[arg(required = true)]
+    solution: Option<u32>,
+    #[arg(required = true)]
+    input_path: Option<PathBuf>,
+    #[command(flatten)]
+    options: O,
+    #[command(subcommand)]
+    command: Option<C>,
 }
 
+// Options for days that don't take any beyond the solution and input path.
+#[derive(Debug, Default, clap::Args)]
+pub struct NoOptions {}
+
 fn init_tracing() {
     // Only run once; protects against multiple initialization attempts
     static INIT: std::sync::Once = std::sync::Once::new();
@@ -25,19 +52,93 @@ fn init_tracing() {
 }
 
 type SolutionFunction = fn(&File) -> Result<u64>;
+type SolutionWithOptionsFunction<O> = fn(&File, &O) -> Result<u64>;
 
 pub fn shared_main(solution1: SolutionFunction, solution2: SolutionFunction) -> Result<()> {
+    run::<NoOptions>(|solution, input, _| match solution {
+        1 => solution1(input),
+        2 => solution2(input),
+        default => panic!("Invalid solution index: {default}"),
+    })
+}
+
+// Same as `shared_main`, but also parses day specific options `O` from the
+// command line and hands them to the solutions.
+pub fn shared_main_with_options<O: clap::Args + Debug>(
+    solution1: SolutionWithOptionsFunction<O>,
+    solution2: SolutionWithOptionsFunction<O>,
+) -> Result<()> {
+    run::<O>(|solution, input, options| match solution {
+        1 => solution1(input, options),
+        2 => solution2(input, options),
+        default => panic!("Invalid solution index: {default}"),
+    })
+}
+
+// Same as `shared_main_with_options`, but the day can also be run as one of
+// its commands `C` instead of a solution, e.g. `day5 serve input.txt`.
+pub fn shared_main_with_commands<O: clap::Args + Debug, C: clap::Subcommand + Debug>(
+    solution1: SolutionWithOptionsFunction<O>,
+    solution2: SolutionWithOptionsFunction<O>,
+    command: fn(&C) -> Result<()>,
+) -> Result<()> {
     init_tracing();
-    let args = Args::parse();
+    let args = CommandArgs::<O, C>::parse();
     info!("Input arguments: {:?}", args);
 
-    let input = File::open(args.input_path).with_context(|| "Unable to open input file")?;
-    let value = match args.solution {
-        1 => solution1(&input).with_context(|| "Exception encountered with executing solution")?,
-        2 => solution2(&input).with_context(|| "Exception encountered with executing solution")?,
-        default => panic!("Invalid solution index: {default}"),
-    };
+    match (args.command, args.solution, args.input_path) {
+        (Some(subcommand), _, _) => {
+            command(&subcommand).with_context(|| "Exception encountered with executing command")
+        }
+        (None, Some(solution), Some(input_path)) => solve(
+            solution,
+            &input_path,
+            &args.options,
+            |solution, input, options| match solution {
+                1 => solution1(input, options),
+                2 => solution2(input, options),
+                default => panic!("Invalid solution index: {default}"),
+            },
+        ),
+        // clap requires both when there is no command.
+        (None, _, _) => unreachable!(),
+    }
+}
+
+fn run<O: clap::Args + Debug>(solve_with: impl Fn(u32, &File, &O) -> Result<u64>) -> Result<()> {
+    init_tracing();
+    let args = Args::<O>::parse();
+    info!("Input arguments: {:?}", args);
+
+    solve(args.solution, &args.input_path, &args.options, solve_with)
+}
+
+fn solve<O>(
+    solution: u32,
+    input_path: &Path,
+    options: &O,
+    solve_with: impl Fn(u32, &File, &O) -> Result<u64>,
+) -> Result<()> {
+    let input = File::open(input_path).with_context(|| "Unable to open input file")?;
+    let value = solve_with(solution, &input, options)
+        .with_context(|| "Exception encountered with executing solution")?;
     info!("Solution: {value}");
 
     Ok(())
 }
+
+// CRC-32 (the IEEE polynomial used by PNG and zip) of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,26 @@
+// Helpers for the days' tests. These can't sit behind `#[cfg(test)]`, since the
+// days are separate crates that build this library without it.
+
+// A seeded xorshift generator, for deterministic pseudo random test inputs.
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on a state of 0.
+        XorShift { state: seed | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // A value below `bound`.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}